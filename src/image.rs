@@ -1,17 +1,37 @@
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::ops;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use image::{Rgb, RgbImage};
+use half::f16;
+use image::error::{ParameterError, ParameterErrorKind};
+use image::imageops::{self, FilterType};
+use image::{
+    ImageBuffer, ImageError, ImageFormat, ImageResult, Rgb, Rgb32FImage, RgbImage, Rgba, RgbaImage,
+};
+use rayon::prelude::*;
+use rayon::{ThreadPoolBuildError, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
 
-use crate::object::{Point, Ray, ScatteredRay, Vec3, World};
-use crate::utils::Interval;
+use crate::object::{
+    HitRecord, Material, MaterialType, Point, Ray, ScatteredRay, Sphere, Vec3, World,
+};
+use crate::texture::ImageTexture;
+use crate::utils::{self, Interval};
 
 // Maximum value contained in an RGB channel
 pub const MAX_COLOR_CHANNEL_VALUE: u8 = 255;
-// P3 means the file contains a portable pixmap image written in ASCII
-// https://en.wikipedia.org/wiki/Netpbm#Description
 const MINIMUM_DISTANCE_AGAINST_SHADOW_ACNE: f64 = 0.0001;
+// Magic bytes identifying a raw half float dump written by `save_f16`
+pub const F16_MAGIC: &[u8; 4] = b"RF16";
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+/// Stored in scene files as the array of its channels, in [0,255]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(from = "[u8; 3]", into = "[u8; 3]")]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -19,46 +39,198 @@ pub struct Color {
 }
 
 impl Color {
-    fn black() -> Color {
+    pub fn black() -> Color {
         Color { r: 0, g: 0, b: 0 }
     }
 
-    fn mean_color(colors: Vec<Color>) -> Color {
-        let mut r: u16 = 0;
-        let mut g: u16 = 0;
-        let mut b: u16 = 0;
-        for color in &colors {
-            r += color.r as u16;
-            g += color.g as u16;
-            b += color.b as u16;
-        }
-        r /= colors.len() as u16;
-        g /= colors.len() as u16;
-        b /= colors.len() as u16;
+    pub fn white() -> Color {
         Color {
-            r: r as u8,
-            g: g as u8,
-            b: b as u8,
+            r: MAX_COLOR_CHANNEL_VALUE,
+            g: MAX_COLOR_CHANNEL_VALUE,
+            b: MAX_COLOR_CHANNEL_VALUE,
         }
     }
-    fn channel_gamma_correction(color: u8) -> u8 {
-        if color > 0 {
-            f64::sqrt(color as f64) as u8
-        } else {
-            color
+
+    /// Linear interpolation from `a` (t = 0) to `b` (t = 1), computed in floating point and
+    /// rounded once at the end.
+    pub fn lerp(a: Color, b: Color, t: f64) -> Color {
+        (Color3::from(a) * (1.0 - t) + Color3::from(b) * t).to_color()
+    }
+}
+
+/// Linear color with floating point channels, 1 being the brightest displayable value.
+/// Channels aren't clamped, so that light can be accumulated without losing dynamic range, and
+/// are only quantized to a `Color` for display.
+/// Stored in scene files as the array of its channels.
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(from = "[f64; 3]", into = "[f64; 3]")]
+pub struct Color3 {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+}
+
+impl Color3 {
+    pub fn black() -> Color3 {
+        Color3::default()
+    }
+
+    pub fn white() -> Color3 {
+        Color3 {
+            r: 1.,
+            g: 1.,
+            b: 1.,
+        }
+    }
+
+    pub fn is_black(&self) -> bool {
+        *self == Color3::black()
+    }
+
+    /// Same color with NaN and infinite channels replaced by 0, and the others clamped to
+    /// `max`
+    pub fn sanitized(self, max: f64) -> Color3 {
+        let channel = |value: f64| {
+            if value.is_finite() {
+                value.min(max)
+            } else {
+                0.
+            }
+        };
+        Color3 {
+            r: channel(self.r),
+            g: channel(self.g),
+            b: channel(self.b),
+        }
+    }
+
+    /// Translate the color values from linear space to gamma 2 space
+    pub fn gamma_corrected(self) -> Color3 {
+        let correct = |linear: f64| linear.max(0.).sqrt();
+        Color3 {
+            r: correct(self.r),
+            g: correct(self.g),
+            b: correct(self.b),
+        }
+    }
+
+    /// Translate the color values from linear space with the sRGB transfer curve, the one
+    /// displays expect. `gamma_corrected` approximates it with a power of 1/2.
+    pub fn srgb_encoded(self) -> Color3 {
+        let encode = |linear: f64| {
+            let linear = linear.max(0.);
+            if linear <= 0.0031308 {
+                12.92 * linear
+            } else {
+                1.055 * linear.powf(1. / 2.4) - 0.055
+            }
+        };
+        Color3 {
+            r: encode(self.r),
+            g: encode(self.g),
+            b: encode(self.b),
         }
     }
 
-    /// Translate the color values from linear space to gamma space
-    fn gamma_corrected(self) -> Color {
+    /// Closest 16 bit pixel, channels are clamped to [0,1]
+    pub fn to_rgb16(self) -> Rgb<u16> {
+        let channel = |value: f64| (value.clamp(0.0, 1.0) * u16::MAX as f64).round() as u16;
+        Rgb([channel(self.r), channel(self.g), channel(self.b)])
+    }
+
+    /// Closest displayable color, channels are clamped to [0,1]
+    pub fn to_color(self) -> Color {
+        let channel =
+            |value: f64| (value.clamp(0.0, 1.0) * MAX_COLOR_CHANNEL_VALUE as f64).round() as u8;
         Color {
-            r: Color::channel_gamma_correction(self.r),
-            g: Color::channel_gamma_correction(self.g),
-            b: Color::channel_gamma_correction(self.b),
+            r: channel(self.r),
+            g: channel(self.g),
+            b: channel(self.b),
+        }
+    }
+}
+
+impl From<[u8; 3]> for Color {
+    fn from([r, g, b]: [u8; 3]) -> Self {
+        Color { r, g, b }
+    }
+}
+
+impl From<Color> for [u8; 3] {
+    fn from(color: Color) -> Self {
+        [color.r, color.g, color.b]
+    }
+}
+
+impl From<[f64; 3]> for Color3 {
+    fn from([r, g, b]: [f64; 3]) -> Self {
+        Color3 { r, g, b }
+    }
+}
+
+impl From<Color3> for [f64; 3] {
+    fn from(color: Color3) -> Self {
+        [color.r, color.g, color.b]
+    }
+}
+
+impl From<Color> for Color3 {
+    fn from(color: Color) -> Self {
+        let channel = |value: u8| value as f64 / MAX_COLOR_CHANNEL_VALUE as f64;
+        Color3 {
+            r: channel(color.r),
+            g: channel(color.g),
+            b: channel(color.b),
+        }
+    }
+}
+
+impl ops::Add<Color3> for Color3 {
+    type Output = Color3;
+    fn add(self, rhs: Color3) -> Self::Output {
+        Color3 {
+            r: self.r + rhs.r,
+            g: self.g + rhs.g,
+            b: self.b + rhs.b,
         }
     }
 }
 
+impl ops::AddAssign<Color3> for Color3 {
+    fn add_assign(&mut self, rhs: Color3) {
+        *self = *self + rhs;
+    }
+}
+
+impl ops::Mul<Color3> for Color3 {
+    type Output = Color3;
+    fn mul(self, rhs: Color3) -> Self::Output {
+        Color3 {
+            r: self.r * rhs.r,
+            g: self.g * rhs.g,
+            b: self.b * rhs.b,
+        }
+    }
+}
+
+impl ops::Mul<f64> for Color3 {
+    type Output = Color3;
+    fn mul(self, rhs: f64) -> Self::Output {
+        Color3 {
+            r: self.r * rhs,
+            g: self.g * rhs,
+            b: self.b * rhs,
+        }
+    }
+}
+
+impl ops::Div<f64> for Color3 {
+    type Output = Color3;
+    fn div(self, rhs: f64) -> Self::Output {
+        self * (1. / rhs)
+    }
+}
+
 impl From<[f64; 3]> for Color {
     /// Accepts a triplet of floats in the interval [0,1].
     /// Out of interval values will be clamped
@@ -67,19 +239,24 @@ impl From<[f64; 3]> for Color {
 
         Color {
             r: (normalized[0] * MAX_COLOR_CHANNEL_VALUE as f64) as u8,
-            b: (normalized[1] * MAX_COLOR_CHANNEL_VALUE as f64) as u8,
-            g: (normalized[2] * MAX_COLOR_CHANNEL_VALUE as f64) as u8,
+            g: (normalized[1] * MAX_COLOR_CHANNEL_VALUE as f64) as u8,
+            b: (normalized[2] * MAX_COLOR_CHANNEL_VALUE as f64) as u8,
         }
     }
 }
 
+/// Scales a channel by `k`, saturating to the `u8` range instead of wrapping.
+fn scale_channel(channel: u8, k: f64) -> u8 {
+    (channel as f64 * k).clamp(0., MAX_COLOR_CHANNEL_VALUE as f64) as u8
+}
+
 impl ops::Mul<f64> for Color {
     type Output = Color;
     fn mul(self, rhs: f64) -> Self::Output {
         Color {
-            r: (self.r as f64 * rhs) as u8,
-            g: (self.g as f64 * rhs) as u8,
-            b: (self.b as f64 * rhs) as u8,
+            r: scale_channel(self.r, rhs),
+            g: scale_channel(self.g, rhs),
+            b: scale_channel(self.b, rhs),
         }
     }
 }
@@ -87,11 +264,7 @@ impl ops::Mul<f64> for Color {
 impl ops::Mul<Color> for f64 {
     type Output = Color;
     fn mul(self, rhs: Color) -> Self::Output {
-        Color {
-            r: (self * rhs.r as f64) as u8,
-            g: (self * rhs.g as f64) as u8,
-            b: (self * rhs.b as f64) as u8,
-        }
+        rhs * self
     }
 }
 
@@ -111,10 +284,12 @@ impl ops::Add<Color> for Color {
 impl ops::Mul<Color> for Color {
     type Output = Color;
     fn mul(self, rhs: Color) -> Self::Output {
-        let r = (self.r as f64 / MAX_COLOR_CHANNEL_VALUE as f64 * rhs.r as f64) as u8;
-        let g = (self.g as f64 / MAX_COLOR_CHANNEL_VALUE as f64 * rhs.g as f64) as u8;
-        let b = (self.b as f64 / MAX_COLOR_CHANNEL_VALUE as f64 * rhs.b as f64) as u8;
-        Color { r, g, b }
+        let max = MAX_COLOR_CHANNEL_VALUE as f64;
+        Color {
+            r: scale_channel(self.r, rhs.r as f64 / max),
+            g: scale_channel(self.g, rhs.g as f64 / max),
+            b: scale_channel(self.b, rhs.b as f64 / max),
+        }
     }
 }
 
@@ -124,6 +299,348 @@ impl From<Color> for Rgb<u8> {
     }
 }
 
+/// Rendered rectangle of an image, positioned by its top left corner (x, y) in the full image.
+pub struct TileResult {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: RgbImage,
+}
+
+/// Order in which the tiles of an image are rendered
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BucketOrder {
+    /// Row by row, from the top left corner
+    TopDown,
+    /// Spiral starting from the center of the image
+    Spiral,
+    /// Along a Hilbert curve, consecutive tiles are always neighbors
+    Hilbert,
+}
+
+impl BucketOrder {
+    /// (column, row) of the tiles of a grid, in the order they should be rendered.
+    /// Every tile of the grid appears exactly once, an empty grid gives no tile.
+    pub fn sequence(&self, columns: u32, rows: u32) -> Vec<(u32, u32)> {
+        let tile_count = (columns * rows) as usize;
+        let mut tiles = Vec::with_capacity(tile_count);
+        if tile_count == 0 {
+            return tiles;
+        }
+        match self {
+            BucketOrder::TopDown => {
+                for row in 0..rows {
+                    for column in 0..columns {
+                        tiles.push((column, row));
+                    }
+                }
+            }
+            BucketOrder::Spiral => {
+                // Walk right 1, down 1, left 2, up 2, right 3... from the center tile, only
+                // keeping the tiles inside the grid.
+                let (mut column, mut row) = ((columns as i64 - 1) / 2, (rows as i64 - 1) / 2);
+                let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+                let mut step = 1;
+                let mut direction = 0;
+                tiles.push((column as u32, row as u32));
+                while tiles.len() < tile_count {
+                    // Each step length is walked twice, in two consecutive directions
+                    for _ in 0..2 {
+                        let (dx, dy) = directions[direction % 4];
+                        for _ in 0..step {
+                            column += dx;
+                            row += dy;
+                            if (0..columns as i64).contains(&column)
+                                && (0..rows as i64).contains(&row)
+                            {
+                                tiles.push((column as u32, row as u32));
+                            }
+                        }
+                        direction += 1;
+                    }
+                    step += 1;
+                }
+            }
+            BucketOrder::Hilbert => {
+                // The curve covers a square grid whose side is a power of two, tiles outside
+                // of the image are skipped.
+                let side = columns.max(rows).max(1).next_power_of_two();
+                for d in 0..side * side {
+                    let (column, row) = BucketOrder::hilbert_position(side, d);
+                    if column < columns && row < rows {
+                        tiles.push((column, row));
+                    }
+                }
+            }
+        }
+        tiles
+    }
+
+    /// Position of the `d`-th point of the Hilbert curve covering a square grid of `side` cells
+    fn hilbert_position(side: u32, d: u32) -> (u32, u32) {
+        let (mut x, mut y) = (0, 0);
+        let mut t = d;
+        let mut s = 1;
+        while s < side {
+            let rx = 1 & (t / 2);
+            let ry = 1 & (t ^ rx);
+            // Rotate the quadrant so that the curve stays continuous
+            if ry == 0 {
+                if rx == 1 {
+                    x = s - 1 - x;
+                    y = s - 1 - y;
+                }
+                std::mem::swap(&mut x, &mut y);
+            }
+            x += s * rx;
+            y += s * ry;
+            t /= 4;
+            s *= 2;
+        }
+        (x, y)
+    }
+}
+
+/// Operator compressing linear light into the displayable range before quantization
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    /// Values above 1 are clamped
+    None,
+    /// Reinhard operator c / (1 + c), bright values approach 1 without reaching it
+    Reinhard,
+}
+
+impl ToneMap {
+    pub fn apply(&self, color: Color3) -> Color3 {
+        match self {
+            ToneMap::None => color,
+            ToneMap::Reinhard => {
+                let compress = |c: f64| c / (1. + c);
+                Color3 {
+                    r: compress(color.r),
+                    g: compress(color.g),
+                    b: compress(color.b),
+                }
+            }
+        }
+    }
+}
+
+/// What rays that don't hit any object see
+#[derive(Clone, Debug, PartialEq)]
+pub enum Background {
+    /// Sky, a white to blue gradient from the bottom to the top
+    SkyGradient,
+    /// Vertical gradient, from `bottom` when looking straight down to `top` when looking
+    /// straight up
+    Gradient { top: Color, bottom: Color },
+    /// Uniform color, black for scenes only lit by emissive materials
+    Solid(Color),
+    /// Panorama surrounding the scene, mapped with the same (u, v) as the unit sphere, see
+    /// `Sphere::get_sphere_uv`
+    Equirectangular(ImageTexture),
+}
+
+impl Background {
+    pub fn color(&self, ray: &Ray) -> Color {
+        match self {
+            Background::SkyGradient => Ray::blue_lerp(ray),
+            Background::Gradient { top, bottom } => Ray::gradient_lerp(ray, *bottom, *top),
+            Background::Solid(color) => *color,
+            Background::Equirectangular(panorama) => {
+                let (u, v) = Sphere::get_sphere_uv(&ray.direction.normalized());
+                panorama.value(u, v)
+            }
+        }
+    }
+
+    /// Unclamped light seen by the ray, brighter than white for the bright texels of HDR
+    /// panoramas
+    pub fn radiance(&self, ray: &Ray) -> Color3 {
+        match self {
+            Background::Equirectangular(panorama) => {
+                let (u, v) = Sphere::get_sphere_uv(&ray.direction.normalized());
+                panorama.radiance(u, v)
+            }
+            _ => self.color(ray).into(),
+        }
+    }
+}
+
+/// Settings of a camera, each with a default, to build it without a long argument list.
+/// See `Camera::look_at` for the meaning of each setting.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraBuilder {
+    aspect_ratio: f64,
+    image_width: u32,
+    sample_per_pixel: u32,
+    max_ray_bounces: u16,
+    vfov_degrees: f64,
+    defocus_angle: f64,
+    // Distance from lookfrom to lookat when not set
+    focus_dist: Option<f64>,
+    lookfrom: Point,
+    lookat: Point,
+    vup: Vec3,
+}
+
+impl Default for CameraBuilder {
+    /// Same camera as `Camera::init(16.0 / 9.0, 400, 100, 50, 90.0)`
+    fn default() -> Self {
+        CameraBuilder {
+            aspect_ratio: 16.0 / 9.0,
+            image_width: 400,
+            sample_per_pixel: 100,
+            max_ray_bounces: 50,
+            vfov_degrees: 90.0,
+            defocus_angle: 0.,
+            focus_dist: None,
+            lookfrom: Point {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            lookat: Point {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            vup: Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+        }
+    }
+}
+
+impl CameraBuilder {
+    pub fn aspect_ratio(self, aspect_ratio: f64) -> CameraBuilder {
+        CameraBuilder {
+            aspect_ratio,
+            ..self
+        }
+    }
+
+    pub fn image_width(self, image_width: u32) -> CameraBuilder {
+        CameraBuilder {
+            image_width,
+            ..self
+        }
+    }
+
+    pub fn sample_per_pixel(self, sample_per_pixel: u32) -> CameraBuilder {
+        CameraBuilder {
+            sample_per_pixel,
+            ..self
+        }
+    }
+
+    pub fn max_ray_bounces(self, max_ray_bounces: u16) -> CameraBuilder {
+        CameraBuilder {
+            max_ray_bounces,
+            ..self
+        }
+    }
+
+    pub fn vfov_degrees(self, vfov_degrees: f64) -> CameraBuilder {
+        CameraBuilder {
+            vfov_degrees,
+            ..self
+        }
+    }
+
+    pub fn defocus_angle(self, defocus_angle: f64) -> CameraBuilder {
+        CameraBuilder {
+            defocus_angle,
+            ..self
+        }
+    }
+
+    pub fn focus_dist(self, focus_dist: f64) -> CameraBuilder {
+        CameraBuilder {
+            focus_dist: Some(focus_dist),
+            ..self
+        }
+    }
+
+    pub fn lookfrom(self, lookfrom: Point) -> CameraBuilder {
+        CameraBuilder { lookfrom, ..self }
+    }
+
+    pub fn lookat(self, lookat: Point) -> CameraBuilder {
+        CameraBuilder { lookat, ..self }
+    }
+
+    pub fn vup(self, vup: Vec3) -> CameraBuilder {
+        CameraBuilder { vup, ..self }
+    }
+
+    pub fn build(self) -> Camera {
+        Camera::look_at(
+            self.aspect_ratio,
+            self.image_width,
+            self.sample_per_pixel,
+            self.max_ray_bounces,
+            self.vfov_degrees,
+            self.defocus_angle,
+            self.focus_dist
+                .unwrap_or_else(|| (self.lookfrom - self.lookat).len()),
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+        )
+    }
+}
+
+/// Measures of a render, see `Camera::render_with_stats`
+#[derive(Clone, Copy, Debug)]
+pub struct RenderStats {
+    pub elapsed: Duration,
+    /// Rays shot from the camera, one per sample of each pixel
+    pub primary_rays: u64,
+    /// Every ray traced, primary rays and their bounces
+    pub total_rays: u64,
+}
+
+thread_local! {
+    // Rays traced by the current thread, all of them and the primary ones. A row of pixels is
+    // rendered by a single thread.
+    static RAYS_TRACED: Cell<u64> = const { Cell::new(0) };
+    static PRIMARY_RAYS_TRACED: Cell<u64> = const { Cell::new(0) };
+}
+
+/// How the scene is projected on the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// Rays spread from the camera center, far objects look smaller
+    Perspective,
+    /// Parallel rays along the view direction, objects keep their size whatever their distance.
+    /// `width` is the width of the scene seen by the image. There's no depth of field.
+    Orthographic { width: f64 },
+}
+
+/// Stop sampling a pixel once its color is known precisely enough, see
+/// `Camera::with_adaptive_sampling`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    /// Samples always taken, to estimate the variance of the pixel
+    pub min_samples: u32,
+    /// Sampling stops once the variance of the mean luminance of the pixel is below it
+    pub max_variance: f64,
+}
+
+/// Exponential fog blending the surfaces hit toward `color` with their distance, see
+/// `Camera::with_fog`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fog {
+    pub color: Color,
+    /// Fraction of the light replaced by the fog per unit of distance, for short distances
+    pub density: f64,
+}
+
+#[derive(Clone)]
 pub struct Camera {
     image_width: u32,
     image_height: u32,
@@ -133,14 +650,86 @@ pub struct Camera {
     center: Point,
     sample_per_pixel: u32,
     max_ray_bounces: u16,
+    // Aperture of the lens, the angle of the cone with its apex at the center of the viewport
+    // and its base on the lens disk. 0 disables the depth of field.
+    defocus_angle: f64,
+    // Horizontal and vertical radius vectors of the lens disk
+    defocus_disk_u: Vec3,
+    defocus_disk_v: Vec3,
+    // Color of the rays that don't hit any object
+    background: Background,
+    // Seed of the random samples, renders are reproducible when it's set
+    seed: Option<u64>,
+    tone_map: ToneMap,
+    projection: Projection,
+    adaptive_sampling: Option<AdaptiveSampling>,
+    // Whether diffuse surfaces sample the lights of the world directly
+    next_event_estimation: bool,
+    // Samples brighter than it are clamped to it, against fireflies
+    max_sample_radiance: f64,
+    // Material replacing the ones of all the objects but the lights
+    material_override: Option<Arc<Material>>,
+    fog: Option<Fog>,
 }
 
 impl Camera {
-    fn ray_color(ray: &Ray, world: &World, depth: u16) -> Color {
+    fn ray_color(&self, ray: &Ray, world: &World, depth: u16) -> Color3 {
+        self.path_color(ray, world, depth, false)
+    }
+
+    /// Light carried back along `ray`. When `lights_sampled` is true, the lights of the world
+    /// were sampled directly at the previous bounce, so their emission is ignored if the ray
+    /// hits one of them. Other emissive objects are always counted.
+    fn path_color(&self, ray: &Ray, world: &World, depth: u16, lights_sampled: bool) -> Color3 {
+        RAYS_TRACED.set(RAYS_TRACED.get() + 1);
         if depth == 0 {
-            return Color::black();
+            return Color3::black();
+        }
+        match self.shaded_hit(ray, world) {
+            Some(hit) => self.hit_color(&hit, ray, world, depth, lights_sampled),
+            None => self.background.radiance(ray),
+        }
+    }
+
+    /// Light leaving the hit point of `ray` back along it, see `path_color`
+    fn hit_color(
+        &self,
+        hit: &HitRecord,
+        ray: &Ray,
+        world: &World,
+        depth: u16,
+        lights_sampled: bool,
+    ) -> Color3 {
+        let emitted = if lights_sampled && world.lights_contain(hit.p) {
+            Color3::black()
+        } else {
+            hit.material.emitted()
+        };
+        // Get scattered rays based on the type of material that was hit
+        let scattered_rays = ScatteredRay::scatter(hit, ray);
+        // Diffuse surfaces get the light of the light sources directly, the scattered rays
+        // only bring the indirect light
+        let sample_lights = self.next_event_estimation
+            && !world.lights.is_empty()
+            && matches!(hit.material.material_type, MaterialType::Lambertian(_));
+        let direct = if sample_lights && !scattered_rays.is_empty() {
+            Some(Camera::direct_light(world, hit, ray.time))
+        } else {
+            None
+        };
+        let color = emitted + self.scattered_color(&scattered_rays, world, depth, direct);
+        match self.fog {
+            Some(fog) => {
+                let transmittance = (-fog.density * (hit.p - ray.origin).len()).exp();
+                color * transmittance + Color3::from(fog.color) * (1. - transmittance)
+            }
+            None => color,
         }
-        if let Some(hit) = world.hit(
+    }
+
+    /// Closest hit of the ray, with the material it is shaded with
+    fn shaded_hit(&self, ray: &Ray, world: &World) -> Option<HitRecord> {
+        let mut hit = world.hit(
             ray,
             Interval {
                 // Because of floating rounding error, the origin of the reflected Ray might be
@@ -151,56 +740,187 @@ impl Camera {
                 min: MINIMUM_DISTANCE_AGAINST_SHADOW_ACNE,
                 max: f64::INFINITY,
             },
-        ) {
-            // Get scattered ray based on the type of material that was hit
-            let scattered_ray = ScatteredRay::scatter(&hit, ray);
-            scattered_ray.attenuation * Camera::ray_color(&scattered_ray.ray, world, depth - 1)
-        } else {
-            Ray::blue_lerp(ray)
+        )?;
+        if let Some(material) = &self.material_override {
+            // Lights keep shining
+            if hit.material.emitted().is_black() {
+                hit.material = Arc::clone(material);
+            }
+        }
+        Some(hit)
+    }
+
+    /// Average light brought by the rays scattered at a hit, black when the light was absorbed.
+    /// `direct` is the light sampled directly from the lights at the hit, if it was, in which
+    /// case the emission of the lights met by the scattered rays isn't counted again.
+    fn scattered_color(
+        &self,
+        scattered_rays: &[ScatteredRay],
+        world: &World,
+        depth: u16,
+        direct: Option<Color3>,
+    ) -> Color3 {
+        if scattered_rays.is_empty() {
+            return Color3::black();
+        }
+        let total = scattered_rays
+            .iter()
+            .map(|scattered_ray| {
+                let attenuation = scattered_ray.attenuation;
+                let indirect =
+                    self.path_color(&scattered_ray.ray, world, depth - 1, direct.is_some());
+                attenuation * direct.unwrap_or(Color3::black()) + attenuation * indirect
+            })
+            .fold(Color3::black(), |total, color| total + color);
+        total / scattered_rays.len() as f64
+    }
+
+    /// Number of rays traced along the path starting with `ray`, until it reaches the
+    /// background, gets absorbed, or `max_ray_bounces` rays were traced. Only the first ray
+    /// scattered at each hit is followed.
+    fn path_length(&self, mut ray: Ray, world: &World) -> u16 {
+        let mut length = 0;
+        while length < self.max_ray_bounces {
+            length += 1;
+            match self
+                .shaded_hit(&ray, world)
+                .and_then(|hit| ScatteredRay::scatter(&hit, &ray).into_iter().next())
+            {
+                Some(scattered_ray) => ray = scattered_ray.ray,
+                None => break,
+            }
+        }
+        length
+    }
+
+    /// Estimate of the light received by a diffuse surface from a point sampled on one of the
+    /// lights of the world, picked at random. It's 0 when the point is hidden by another object.
+    /// Multiplied by the albedo of the surface, it gives the light reflected by the surface.
+    fn direct_light(world: &World, hit: &HitRecord, time: f64) -> Color3 {
+        let index = ((utils::random::<f64>() * world.lights.len() as f64) as usize)
+            .min(world.lights.len() - 1);
+        let light = &world.lights[index];
+        let point = light.sample_point();
+
+        let to_light = point - hit.p;
+        let distance_squared = to_light.length_squared();
+        let direction = to_light / distance_squared.sqrt();
+        let cos_surface = hit.normal.dot(&direction);
+        let light_normal = light.quad.u.cross(&light.quad.v);
+        let area = light_normal.len();
+        // Lights emit from both sides
+        let cos_light = (light_normal / area).dot(&direction).abs();
+        if cos_surface <= 0. || cos_light < 1e-8 {
+            return Color3::black();
+        }
+
+        // The light is at t = 1 along the shadow ray, anything closer hides it
+        let shadow_ray = Ray {
+            origin: hit.p,
+            direction: to_light,
+            time,
+        };
+        let shadow_interval = Interval {
+            min: MINIMUM_DISTANCE_AGAINST_SHADOW_ACNE,
+            max: 1. - MINIMUM_DISTANCE_AGAINST_SHADOW_ACNE,
+        };
+        if world.hit(&shadow_ray, shadow_interval).is_some() {
+            return Color3::black();
         }
+
+        // Lambertian BRDF (1 / pi) times the change of variables from the solid angle to the
+        // area of the light, over the probability of sampling this point. Shadow rays aimed at
+        // a part of the light still count the whole area, so the softness doesn't dim the light.
+        let weight = cos_surface * cos_light * area * world.lights.len() as f64
+            / (std::f64::consts::PI * distance_squared);
+        light.quad.material.emitted() * weight
+    }
+
+    /// Builder starting from the default settings, see `CameraBuilder`.
+    pub fn builder() -> CameraBuilder {
+        CameraBuilder::default()
     }
 
+    /// Camera at the origin, looking toward +x.
+    /// `vfov_degrees` is the vertical field of view, the angle between the top and the bottom
+    /// edges of the image.
     pub fn init(
         aspect_ratio: f64,
         image_width: u32,
         sample_per_pixel: u32,
         max_ray_bounces: u16,
+        vfov_degrees: f64,
+    ) -> Camera {
+        Camera::look_at(
+            aspect_ratio,
+            image_width,
+            sample_per_pixel,
+            max_ray_bounces,
+            vfov_degrees,
+            0.,
+            1.,
+            Point {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            Point {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+        )
+    }
+
+    /// Camera placed at `lookfrom`, aimed at `lookat`.
+    /// `vup` is the "up" direction of the camera, it sets the roll of the camera around its
+    /// view direction.
+    /// Objects at `focus_dist` from the camera are sharp, the others get blurrier with the
+    /// distance to that plane, the more so the bigger `defocus_angle` (in degrees) is.
+    #[allow(clippy::too_many_arguments)]
+    pub fn look_at(
+        aspect_ratio: f64,
+        image_width: u32,
+        sample_per_pixel: u32,
+        max_ray_bounces: u16,
+        vfov_degrees: f64,
+        defocus_angle: f64,
+        focus_dist: f64,
+        lookfrom: Point,
+        lookat: Point,
+        vup: Vec3,
     ) -> Camera {
         let image_height = (image_width as f64 / aspect_ratio) as u32;
         let image_height = if image_height < 1 { 1 } else { image_height };
 
-        // Viewport
-        let focal_length = 1.0;
-        let viewport_height = 2.0;
-        let viewport_width = viewport_height * (image_width / image_height) as f64;
-        let camera_center = Point {
-            x: 0.,
-            y: 0.,
-            z: 0.,
-        };
+        // Orthonormal basis of the camera
+        // w: opposite of the view direction, u: right of the camera, v: up of the camera
+        let w = (lookfrom - lookat).normalized();
+        let u = vup.cross(&w).normalized();
+        let v = w.cross(&u);
 
-        let viewport_u = Vec3 {
-            x: 0.,
-            y: 0.,
-            z: viewport_width,
-        };
-        let viewport_v = Vec3 {
-            x: 0.,
-            y: -viewport_height,
-            z: 0.,
-        };
+        // Viewport, placed on the plane of perfect focus
+        // Half of the viewport height over the focus distance is the tangent of half the fov
+        let viewport_height = 2.0 * (vfov_degrees / 2.0).to_radians().tan() * focus_dist;
+        let viewport_width = viewport_height * (image_width as f64 / image_height as f64);
+
+        // Vectors across the horizontal edge of the viewport, and down its vertical edge
+        let viewport_u = viewport_width * u;
+        let viewport_v = viewport_height * -v;
 
         let pixel_delta_u = viewport_u / image_width as f64;
         let pixel_delta_v = viewport_v / image_height as f64;
-        let viewport_upper_left = Vec3 {
-            x: focal_length,
-            y: 0.,
-            z: 0.,
-        } - viewport_u / 2.
-            - viewport_v / 2.;
+        let viewport_upper_left = lookfrom - focus_dist * w - viewport_u / 2. - viewport_v / 2.;
         // Position of the center of the pixel at location (0,0).
         let pixel_00_loc = viewport_upper_left + 0.5 * (pixel_delta_v + pixel_delta_u);
 
+        let defocus_radius = focus_dist * (defocus_angle / 2.0).to_radians().tan();
+
         Camera {
             sample_per_pixel,
             image_width,
@@ -208,69 +928,1073 @@ impl Camera {
             pixel_00_loc,
             pixel_delta_u,
             pixel_delta_v,
-            center: camera_center,
+            center: lookfrom,
             max_ray_bounces,
+            defocus_angle,
+            defocus_disk_u: defocus_radius * u,
+            defocus_disk_v: defocus_radius * v,
+            background: Background::SkyGradient,
+            seed: None,
+            tone_map: ToneMap::None,
+            projection: Projection::Perspective,
+            adaptive_sampling: None,
+            next_event_estimation: false,
+            max_sample_radiance: f64::INFINITY,
+            material_override: None,
+            fog: None,
         }
     }
 
-    pub fn render(&self, world: &World, gamma_corrected: bool) -> RgbImage {
-        // Image content
-        let mut img = RgbImage::new(self.image_width, self.image_height);
-        // Get the color of each pixel
-        // For each pixel, we're going to sample multiple colors
-        for y in 0..self.image_height {
-            for x in 0..self.image_width {
-                let mut sampled_colors: Vec<Color> =
-                    Vec::with_capacity(self.sample_per_pixel as usize);
-                for _ in 0..self.sample_per_pixel {
-                    let ray = self.get_ray(y as usize, x as usize);
-                    sampled_colors.push(Camera::ray_color(&ray, world, self.max_ray_bounces));
-                }
+    /// Replace the background, the sky gradient by default.
+    pub fn with_background(self, background: Background) -> Camera {
+        Camera { background, ..self }
+    }
 
-                let color = if gamma_corrected {
-                    Color::mean_color(sampled_colors).gamma_corrected()
-                } else {
-                    Color::mean_color(sampled_colors)
-                };
-                img.put_pixel(x, y, color.into());
-            }
+    /// Replace the tone mapping of the rendered colors, none by default.
+    pub fn with_tone_map(self, tone_map: ToneMap) -> Camera {
+        Camera { tone_map, ..self }
+    }
+
+    /// Replace the projection, perspective by default.
+    pub fn with_projection(self, projection: Projection) -> Camera {
+        Camera { projection, ..self }
+    }
+
+    /// Sample each pixel until its color converges rather than always taking
+    /// `sample_per_pixel` samples, which becomes the maximum. Flat areas then take fewer samples
+    /// than noisy ones.
+    pub fn with_adaptive_sampling(self, adaptive_sampling: AdaptiveSampling) -> Camera {
+        Camera {
+            adaptive_sampling: Some(adaptive_sampling),
+            ..self
         }
+    }
 
-        img
+    /// Sample the lights of the world (see `World::add_light`) directly from diffuse surfaces,
+    /// on top of the scattered rays. It converges much faster for small lights, especially when
+    /// the background is dark. Disabled by default.
+    pub fn with_next_event_estimation(self, next_event_estimation: bool) -> Camera {
+        Camera {
+            next_event_estimation,
+            ..self
+        }
     }
 
-    /// Construct a camera ray originating from the origin and directed at randomly sampled
-    /// point around the pixel location (row, column) to prevent aliasing.
-    /// Sampling around a pixel will prevent the "stair" like on edges of objects.
-    fn get_ray(&self, row: usize, column: usize) -> Ray {
-        let offset = Camera::sample_square();
-        let pixel_sample = self.pixel_00_loc
-            + (column as f64 + offset.z) * self.pixel_delta_u
-            + (row as f64 + offset.y) * self.pixel_delta_v;
-        let origin = self.center;
-        let direction = pixel_sample - origin;
-        Ray { origin, direction }
+    /// Clamp the channels of each sample to `max_sample_radiance` before averaging them, so that
+    /// a rare very bright path doesn't leave a bright dot (firefly). Unclamped by default.
+    pub fn with_firefly_clamp(self, max_sample_radiance: f64) -> Camera {
+        Camera {
+            max_sample_radiance,
+            ..self
+        }
     }
 
-    // Returns the vector to a random point in the [-.5,-.5];[+.5,+.5] unit square.
-    fn sample_square() -> Vec3 {
-        Vec3 {
-            x: 0.,
-            y: rand::random::<f64>() - 0.5, // rand::random::<f64> output is in [0;1[
-            z: rand::random::<f64>() - 0.5,
+    /// Shade every object with `material` instead of its own, such as a matte clay for quick
+    /// previews. Lights keep their own material so that the scene stays lit.
+    pub fn with_material_override(self, material: Arc<Material>) -> Camera {
+        Camera {
+            material_override: Some(material),
+            ..self
         }
     }
-}
 
+    /// Fade the surfaces hit toward the color of the fog with their distance, by
+    /// `1 - exp(-density * distance)`. It's cheaper than a constant medium, and the background
+    /// stays clear. No fog by default.
+    pub fn with_fog(self, fog: Fog) -> Camera {
+        Camera {
+            fog: Some(fog),
+            ..self
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Draw the random samples from `seed`, so that rendering the same scene twice gives the
+    /// same image.
+    pub fn with_seed(self, seed: u64) -> Camera {
+        Camera {
+            seed: Some(seed),
+            ..self
+        }
+    }
 
-    #[test]
-    fn color_mul_f64() {
-        let color = Color {
-            r: 100,
+    pub fn render(&self, world: &World, gamma_corrected: bool) -> RgbImage {
+        self.render_with_progress(world, gamma_corrected, |_, _| {})
+    }
+
+    /// Render a fast aliased preview, with a single ray through the center of each pixel
+    /// whatever `sample_per_pixel`, so edges are crisp instead of antialiased. The lens and the
+    /// materials are still sampled at random.
+    pub fn render_preview(&self, world: &World, gamma_corrected: bool) -> RgbImage {
+        let preview = Camera {
+            sample_per_pixel: 1,
+            adaptive_sampling: None,
+            ..self.clone()
+        };
+        let rows: Vec<Vec<Color>> = (0..self.image_height)
+            .into_par_iter()
+            .map(|y| {
+                (0..self.image_width)
+                    .map(|x| {
+                        preview.mean_sampled_color(world, x, y, gamma_corrected, |_| {
+                            preview.ray_through(
+                                (x as f64 + 0.5) / self.image_width as f64,
+                                (y as f64 + 0.5) / self.image_height as f64,
+                            )
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        RgbImage::from_fn(self.image_width, self.image_height, |x, y| {
+            rows[y as usize][x as usize].into()
+        })
+    }
+
+    /// Render the image unless `should_stop` gets set, in which case the rows left aren't
+    /// rendered and None is returned. The flag is checked before each row.
+    pub fn render_cancellable(
+        &self,
+        world: &World,
+        gamma_corrected: bool,
+        should_stop: &AtomicBool,
+    ) -> Option<RgbImage> {
+        let rows: Option<Vec<Vec<Color>>> = (0..self.image_height)
+            .into_par_iter()
+            .map(|y| {
+                if should_stop.load(Ordering::Relaxed) {
+                    return None;
+                }
+                Some(
+                    (0..self.image_width)
+                        .map(|x| self.pixel_color(world, x, y, gamma_corrected))
+                        .collect(),
+                )
+            })
+            .collect();
+        let rows = rows?;
+
+        Some(RgbImage::from_fn(
+            self.image_width,
+            self.image_height,
+            |x, y| rows[y as usize][x as usize].into(),
+        ))
+    }
+
+    /// Render the image with at most `threads` worker threads, instead of one per CPU.
+    pub fn render_with_threads(
+        &self,
+        world: &World,
+        gamma_corrected: bool,
+        threads: usize,
+    ) -> Result<RgbImage, ThreadPoolBuildError> {
+        let pool = ThreadPoolBuilder::new().num_threads(threads).build()?;
+        Ok(pool.install(|| self.render(world, gamma_corrected)))
+    }
+
+    /// Render the image, `on_progress` is called with the number of rows done and the total
+    /// number of rows each time a row is completed.
+    pub fn render_with_progress(
+        &self,
+        world: &World,
+        gamma_corrected: bool,
+        on_progress: impl FnMut(u32, u32) + Send,
+    ) -> RgbImage {
+        // Rows complete in any order, they are only counted
+        let progress = Mutex::new((0, on_progress));
+        // Get the color of each pixel, rows are rendered in parallel
+        // For each pixel, we're going to sample multiple colors
+        let rows: Vec<Vec<Color>> = (0..self.image_height)
+            .into_par_iter()
+            .map(|y| {
+                let row = (0..self.image_width)
+                    .map(|x| self.pixel_color(world, x, y, gamma_corrected))
+                    .collect();
+                let (done, on_progress) = &mut *progress.lock().unwrap();
+                *done += 1;
+                on_progress(*done, self.image_height);
+                row
+            })
+            .collect();
+
+        RgbImage::from_fn(self.image_width, self.image_height, |x, y| {
+            rows[y as usize][x as usize].into()
+        })
+    }
+
+    /// Render the image, along with its duration and the number of rays traced.
+    pub fn render_with_stats(
+        &self,
+        world: &World,
+        gamma_corrected: bool,
+    ) -> (RgbImage, RenderStats) {
+        let start = Instant::now();
+        let rows: Vec<(Vec<Color>, u64, u64)> = (0..self.image_height)
+            .into_par_iter()
+            .map(|y| {
+                let rays_before = RAYS_TRACED.get();
+                let primary_rays_before = PRIMARY_RAYS_TRACED.get();
+                let row = (0..self.image_width)
+                    .map(|x| self.pixel_color(world, x, y, gamma_corrected))
+                    .collect();
+                (
+                    row,
+                    PRIMARY_RAYS_TRACED.get() - primary_rays_before,
+                    RAYS_TRACED.get() - rays_before,
+                )
+            })
+            .collect();
+        let elapsed = start.elapsed();
+
+        let image = RgbImage::from_fn(self.image_width, self.image_height, |x, y| {
+            rows[y as usize].0[x as usize].into()
+        });
+        let stats = RenderStats {
+            elapsed,
+            primary_rays: rows.iter().map(|(_, primary_rays, _)| primary_rays).sum(),
+            total_rays: rows.iter().map(|(_, _, rays)| rays).sum(),
+        };
+        (image, stats)
+    }
+
+    /// Render the image with an alpha channel: opaque where the pixel sees an object, and
+    /// transparent where it sees the background, for compositing the render over other images.
+    /// Edge pixels get the fraction of their samples hitting an object, see `pixel_rgba`.
+    pub fn render_rgba(&self, world: &World, gamma_corrected: bool) -> RgbaImage {
+        let rows: Vec<Vec<Rgba<u8>>> = (0..self.image_height)
+            .into_par_iter()
+            .map(|y| {
+                (0..self.image_width)
+                    .map(|x| self.pixel_rgba(world, x, y, gamma_corrected))
+                    .collect()
+            })
+            .collect();
+
+        RgbaImage::from_fn(self.image_width, self.image_height, |x, y| {
+            rows[y as usize][x as usize]
+        })
+    }
+
+    /// Render the image one sample per pixel at a time, for previews refining over time. Each
+    /// item is the running average of the passes done so far, the last one after
+    /// `sample_per_pixel` passes being the same image as `render`. Adaptive sampling is ignored.
+    pub fn render_progressive<'a>(
+        &'a self,
+        world: &'a World,
+        gamma_corrected: bool,
+    ) -> impl Iterator<Item = RgbImage> + 'a {
+        let width = self.image_width as usize;
+        let mut sums = vec![Color3::black(); width * self.image_height as usize];
+        (0..self.sample_per_pixel).map(move |sample| {
+            sums.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+                for (x, sum) in row.iter_mut().enumerate() {
+                    self.seed_sample(x as u32, y as u32, sample);
+                    let ray = self.get_ray(y, x, sample);
+                    *sum += self.sample_color(world, &ray);
+                }
+            });
+            let passes = (sample + 1) as f64;
+            RgbImage::from_fn(self.image_width, self.image_height, |x, y| {
+                let sum = sums[y as usize * width + x as usize];
+                self.displayed(sum / passes, gamma_corrected)
+                    .to_color()
+                    .into()
+            })
+        })
+    }
+
+    /// Render the image with 16 bits per channel, to avoid banding in smooth gradients.
+    pub fn render_16bit(
+        &self,
+        world: &World,
+        gamma_corrected: bool,
+    ) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+        let rows: Vec<Vec<Color3>> = (0..self.image_height)
+            .into_par_iter()
+            .map(|y| {
+                (0..self.image_width)
+                    .map(|x| self.displayed(self.pixel_radiance(world, x, y), gamma_corrected))
+                    .collect()
+            })
+            .collect();
+
+        ImageBuffer::from_fn(self.image_width, self.image_height, |x, y| {
+            rows[y as usize][x as usize].to_rgb16()
+        })
+    }
+
+    /// Render the linear light received by each pixel, without clamping nor gamma correction,
+    /// to be tonemapped later.
+    pub fn render_hdr(&self, world: &World) -> Rgb32FImage {
+        let rows: Vec<Vec<Color3>> = (0..self.image_height)
+            .into_par_iter()
+            .map(|y| {
+                (0..self.image_width)
+                    .map(|x| self.pixel_radiance(world, x, y))
+                    .collect()
+            })
+            .collect();
+
+        Rgb32FImage::from_fn(self.image_width, self.image_height, |x, y| {
+            let color = rows[y as usize][x as usize];
+            Rgb([color.r as f32, color.g as f32, color.b as f32])
+        })
+    }
+
+    /// Render the normals of the surfaces seen through the center of each pixel, each
+    /// coordinate in [-1,1] mapped to a channel in [0,1]. Pixels seeing no object are black.
+    pub fn render_normals(&self, world: &World) -> RgbImage {
+        RgbImage::from_fn(self.image_width, self.image_height, |x, y| {
+            match self.primary_hit(world, x, y) {
+                Some((_, hit)) => Color::from([
+                    (hit.normal.x + 1.) * 0.5,
+                    (hit.normal.y + 1.) * 0.5,
+                    (hit.normal.z + 1.) * 0.5,
+                ]),
+                None => Color::black(),
+            }
+            .into()
+        })
+    }
+
+    /// Render the distance to the surfaces seen through the center of each pixel in grayscale,
+    /// white at the camera and black at `max_depth` or beyond. Pixels seeing no object are
+    /// black. The distance is measured from the origin of the ray of the pixel, which isn't the
+    /// camera center with an orthographic projection.
+    pub fn render_depth(&self, world: &World, max_depth: f64) -> RgbImage {
+        RgbImage::from_fn(self.image_width, self.image_height, |x, y| {
+            let nearness = match self.primary_hit(world, x, y) {
+                Some((ray, hit)) => 1. - (hit.p - ray.origin).len() / max_depth,
+                None => 0.,
+            };
+            Color::from([nearness; 3]).into()
+        })
+    }
+
+    /// Render the number of rays traced along the paths of each pixel, averaged over its
+    /// samples, from blue for a single ray to red for `max_ray_bounces` rays. It shows where
+    /// the render spends its bounces, such as inside dielectrics.
+    pub fn render_bounce_heatmap(&self, world: &World) -> RgbImage {
+        let cold = Color { r: 0, g: 0, b: 255 };
+        let hot = Color { r: 255, g: 0, b: 0 };
+        let rows: Vec<Vec<Color>> = (0..self.image_height)
+            .into_par_iter()
+            .map(|y| {
+                (0..self.image_width)
+                    .map(|x| {
+                        let total: u64 = (0..self.sample_per_pixel)
+                            .map(|sample| {
+                                self.seed_sample(x, y, sample);
+                                let ray = self.get_ray(y as usize, x as usize, sample);
+                                self.path_length(ray, world) as u64
+                            })
+                            .sum();
+                        let mean = total as f64 / self.sample_per_pixel.max(1) as f64;
+                        // A single ray is the shortest possible path
+                        let heat = (mean - 1.) / (self.max_ray_bounces.max(2) - 1) as f64;
+                        Color::lerp(cold, hot, heat.clamp(0., 1.))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        RgbImage::from_fn(self.image_width, self.image_height, |x, y| {
+            rows[y as usize][x as usize].into()
+        })
+    }
+
+    /// Closest hit of the ray through the center of the pixel (x, y), ignoring the depth of
+    /// field, along with that ray
+    fn primary_hit(&self, world: &World, x: u32, y: u32) -> Option<(Ray, HitRecord)> {
+        let ray = self.pinhole_ray(
+            (x as f64 + 0.5) / self.image_width as f64,
+            (y as f64 + 0.5) / self.image_height as f64,
+        );
+        let hit = world.hit(
+            &ray,
+            Interval {
+                min: MINIMUM_DISTANCE_AGAINST_SHADOW_ACNE,
+                max: f64::INFINITY,
+            },
+        )?;
+        Some((ray, hit))
+    }
+
+    /// Render a stereo pair, the images seen by the left and right eyes.
+    /// The eyes are `ipd` (interpupillary distance) apart along the right axis of the camera,
+    /// centered on the camera position. Both look in the same direction.
+    pub fn render_stereo(
+        &self,
+        world: &World,
+        gamma_corrected: bool,
+        ipd: f64,
+    ) -> (RgbImage, RgbImage) {
+        let (left, right) = self.stereo_eyes(ipd);
+        (
+            left.render(world, gamma_corrected),
+            right.render(world, gamma_corrected),
+        )
+    }
+
+    /// Cameras of the left and right eyes, see `render_stereo`.
+    fn stereo_eyes(&self, ipd: f64) -> (Camera, Camera) {
+        let right_axis = self.pixel_delta_u.normalized();
+        let eye = |offset: f64| {
+            let shift = offset * right_axis;
+            Camera {
+                center: self.center + shift,
+                pixel_00_loc: self.pixel_00_loc + shift,
+                ..self.clone()
+            }
+        };
+        (eye(-ipd / 2.), eye(ipd / 2.))
+    }
+
+    /// Render the image tile by tile, `on_tile` is called each time a tile is completed.
+    /// Tiles are square of side `tile_size`, except on the right and bottom edges of the image
+    /// where they are cropped, a `tile_size` of 0 gives tiles of a pixel.
+    /// `order` is the sequence in which the tiles are rendered.
+    pub fn render_tiles(
+        &self,
+        world: &World,
+        gamma_corrected: bool,
+        tile_size: u32,
+        order: BucketOrder,
+        mut on_tile: impl FnMut(TileResult),
+    ) {
+        let tile_size = tile_size.max(1);
+        let columns = self.image_width.div_ceil(tile_size);
+        let rows = self.image_height.div_ceil(tile_size);
+        for (column, row) in order.sequence(columns, rows) {
+            let tile_x = column * tile_size;
+            let tile_y = row * tile_size;
+            let pixels =
+                self.render_region(world, gamma_corrected, tile_x, tile_y, tile_size, tile_size);
+            on_tile(TileResult {
+                x: tile_x,
+                y: tile_y,
+                width: pixels.width(),
+                height: pixels.height(),
+                pixels,
+            });
+        }
+    }
+
+    /// Render the rectangle of the image with its top left corner at (x0, y0), of `width` by
+    /// `height` pixels. Pixels are the same as in the full image, the rectangle is cropped to
+    /// the image bounds.
+    pub fn render_region(
+        &self,
+        world: &World,
+        gamma_corrected: bool,
+        x0: u32,
+        y0: u32,
+        width: u32,
+        height: u32,
+    ) -> RgbImage {
+        let width = width.min(self.image_width.saturating_sub(x0));
+        let height = height.min(self.image_height.saturating_sub(y0));
+        let rows: Vec<Vec<Color>> = (0..height)
+            .into_par_iter()
+            .map(|y| {
+                (0..width)
+                    .map(|x| self.pixel_color(world, x0 + x, y0 + y, gamma_corrected))
+                    .collect()
+            })
+            .collect();
+        RgbImage::from_fn(width, height, |x, y| rows[y as usize][x as usize].into())
+    }
+
+    /// Render the image with primary rays built by `ray_fn` instead of the camera lens, to
+    /// experiment with custom lenses.
+    /// `ray_fn` receives the normalized coordinates (s, t) in [0,1] of the sampled point on the
+    /// image, measured from its left and top edges respectively.
+    /// `|s, t| camera.ray_through(s, t)` gives back the camera output.
+    pub fn render_with_ray_fn(
+        &self,
+        world: &World,
+        gamma_corrected: bool,
+        ray_fn: impl Fn(f64, f64) -> Ray + Sync,
+    ) -> RgbImage {
+        let rows: Vec<Vec<Color>> = (0..self.image_height)
+            .into_par_iter()
+            .map(|y| {
+                (0..self.image_width)
+                    .map(|x| {
+                        self.mean_sampled_color(world, x, y, gamma_corrected, |sample| {
+                            let offset = self.sample_offset(sample);
+                            ray_fn(
+                                (x as f64 + 0.5 + offset.z) / self.image_width as f64,
+                                (y as f64 + 0.5 + offset.y) / self.image_height as f64,
+                            )
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        RgbImage::from_fn(self.image_width, self.image_height, |x, y| {
+            rows[y as usize][x as usize].into()
+        })
+    }
+
+    /// Color of the pixel at (x, y) from its samples hitting an object, with the fraction of
+    /// them as alpha. The samples seeing the background count as black, which gives the color
+    /// premultiplied by the coverage. It's divided back by the coverage in the image, so that
+    /// the edges keep the color of the object instead of mixing in the background.
+    fn pixel_rgba(&self, world: &World, x: u32, y: u32, gamma_corrected: bool) -> Rgba<u8> {
+        let mut premultiplied = Color3::black();
+        let mut hits = 0;
+        for sample in 0..self.sample_per_pixel {
+            self.seed_sample(x, y, sample);
+            let ray = self.get_ray(y as usize, x as usize, sample);
+            if let Some(color) = self.covering_sample_color(world, &ray) {
+                premultiplied += color;
+                hits += 1;
+            }
+        }
+        PRIMARY_RAYS_TRACED.set(PRIMARY_RAYS_TRACED.get() + self.sample_per_pixel as u64);
+
+        let samples = self.sample_per_pixel.max(1) as f64;
+        let coverage = hits as f64 / samples;
+        let color = if hits == 0 {
+            Color::black()
+        } else {
+            self.displayed(premultiplied / samples / coverage, gamma_corrected)
+                .to_color()
+        };
+        let alpha = (coverage * MAX_COLOR_CHANNEL_VALUE as f64).round() as u8;
+        Rgba([color.r, color.g, color.b, alpha])
+    }
+
+    /// Color of the pixel at (x, y), averaged over multiple samples.
+    fn pixel_color(&self, world: &World, x: u32, y: u32, gamma_corrected: bool) -> Color {
+        self.mean_sampled_color(world, x, y, gamma_corrected, |sample| {
+            self.get_ray(y as usize, x as usize, sample)
+        })
+    }
+
+    /// Linear light received by the pixel at (x, y), averaged over multiple samples.
+    fn pixel_radiance(&self, world: &World, x: u32, y: u32) -> Color3 {
+        self.mean_sampled_radiance(world, x, y, |sample| {
+            self.get_ray(y as usize, x as usize, sample)
+        })
+    }
+
+    /// Display color of the average of `sample_per_pixel` rays built by `sample_ray` for the
+    /// pixel (x, y). `sample_ray` receives the index of the sample.
+    fn mean_sampled_color(
+        &self,
+        world: &World,
+        x: u32,
+        y: u32,
+        gamma_corrected: bool,
+        sample_ray: impl FnMut(u32) -> Ray,
+    ) -> Color {
+        let mean = self.mean_sampled_radiance(world, x, y, sample_ray);
+        self.displayed(mean, gamma_corrected).to_color()
+    }
+
+    /// Tone mapped and optionally gamma corrected light, ready to be quantized
+    fn displayed(&self, radiance: Color3, gamma_corrected: bool) -> Color3 {
+        let mapped = self.tone_map.apply(radiance);
+        if gamma_corrected {
+            mapped.gamma_corrected()
+        } else {
+            mapped
+        }
+    }
+
+    /// Average linear light of `sample_per_pixel` rays built by `sample_ray` for the pixel
+    /// (x, y).
+    fn mean_sampled_radiance(
+        &self,
+        world: &World,
+        x: u32,
+        y: u32,
+        sample_ray: impl FnMut(u32) -> Ray,
+    ) -> Color3 {
+        self.sampled_radiance(world, x, y, sample_ray).0
+    }
+
+    /// Average linear light of the rays built by `sample_ray` for the pixel (x, y), along with
+    /// the number of samples taken: `sample_per_pixel`, or fewer with adaptive sampling.
+    fn sampled_radiance(
+        &self,
+        world: &World,
+        x: u32,
+        y: u32,
+        mut sample_ray: impl FnMut(u32) -> Ray,
+    ) -> (Color3, u32) {
+        // Accumulate in linear space, only the mean is quantized
+        let mut sum = Color3::black();
+        // Running mean and sum of squared differences of the luminance (Welford's algorithm)
+        let mut luminance_mean = 0.;
+        let mut luminance_m2 = 0.;
+        let mut samples = 0;
+        while samples < self.sample_per_pixel {
+            self.seed_sample(x, y, samples);
+            let color = self.sample_color(world, &sample_ray(samples));
+            sum += color;
+            samples += 1;
+
+            if let Some(adaptive) = self.adaptive_sampling {
+                let luminance = 0.2126 * color.r + 0.7152 * color.g + 0.0722 * color.b;
+                let delta = luminance - luminance_mean;
+                luminance_mean += delta / samples as f64;
+                luminance_m2 += delta * (luminance - luminance_mean);
+                if samples >= adaptive.min_samples.max(2) {
+                    let variance = luminance_m2 / (samples - 1) as f64;
+                    // Variance of the mean, the noise left in the pixel
+                    if variance / samples as f64 <= adaptive.max_variance {
+                        break;
+                    }
+                }
+            }
+        }
+        PRIMARY_RAYS_TRACED.set(PRIMARY_RAYS_TRACED.get() + samples as u64);
+        (sum / samples.max(1) as f64, samples)
+    }
+
+    /// Restart the random generator for the sample of index `sample` of the pixel (x, y), when
+    /// the camera is seeded. Seeding each sample rather than the whole render keeps the samples
+    /// independent of the order they are taken in, and of the thread taking them.
+    fn seed_sample(&self, x: u32, y: u32, sample: u32) {
+        if let Some(seed) = self.seed {
+            let pixel_seed = utils::hash_seed(seed, x as u64, y as u64);
+            utils::seed_random(utils::hash_seed(pixel_seed, sample as u64, 0));
+        }
+    }
+
+    /// Linear light brought by a single sample ray, or None when it misses every object.
+    /// The primary ray is only intersected once, for both the coverage and the shading.
+    fn covering_sample_color(&self, world: &World, ray: &Ray) -> Option<Color3> {
+        RAYS_TRACED.set(RAYS_TRACED.get() + 1);
+        let hit = self.shaded_hit(ray, world)?;
+        let color = if self.max_ray_bounces == 0 {
+            Color3::black()
+        } else {
+            self.hit_color(&hit, ray, world, self.max_ray_bounces, false)
+        };
+        Some(color.sanitized(self.max_sample_radiance))
+    }
+
+    /// Linear light brought by a single sample ray
+    fn sample_color(&self, world: &World, ray: &Ray) -> Color3 {
+        // A single NaN would poison the whole pixel
+        self.ray_color(ray, world, self.max_ray_bounces)
+            .sanitized(self.max_sample_radiance)
+    }
+
+    /// Construct a camera ray originating from the camera lens and directed at randomly sampled
+    /// point around the pixel location (row, column) to prevent aliasing.
+    /// Sampling around a pixel will prevent the "stair" like on edges of objects.
+    fn get_ray(&self, row: usize, column: usize, sample: u32) -> Ray {
+        let offset = self.sample_offset(sample);
+        self.ray_through(
+            (column as f64 + 0.5 + offset.z) / self.image_width as f64,
+            (row as f64 + 0.5 + offset.y) / self.image_height as f64,
+        )
+    }
+
+    /// Camera ray directed at the point (s, t) of the image, normalized in [0,1] from its left
+    /// and top edges respectively.
+    /// The ray originates from a random point of the lens disk, or from the camera center when
+    /// the depth of field is disabled, at a random time while the shutter is open.
+    pub fn ray_through(&self, s: f64, t: f64) -> Ray {
+        let ray = self.pinhole_ray(s, t);
+        let ray = match self.projection {
+            Projection::Perspective if self.defocus_angle > 0. => {
+                let pixel_sample = ray.origin + ray.direction;
+                let origin = self.defocus_disk_sample();
+                Ray {
+                    origin,
+                    direction: pixel_sample - origin,
+                    ..ray
+                }
+            }
+            _ => ray,
+        };
+        Ray {
+            time: utils::random::<f64>(),
+            ..ray
+        }
+    }
+
+    /// Camera ray directed at the point (s, t) of the image like `ray_through`, without depth
+    /// of field, at time 0.
+    fn pinhole_ray(&self, s: f64, t: f64) -> Ray {
+        // pixel_00_loc is the center of the top left pixel, half a pixel away from the corner
+        let pixel_sample = self.pixel_00_loc
+            + (s * self.image_width as f64 - 0.5) * self.pixel_delta_u
+            + (t * self.image_height as f64 - 0.5) * self.pixel_delta_v;
+        match self.projection {
+            Projection::Perspective => Ray {
+                origin: self.center,
+                direction: pixel_sample - self.center,
+                time: 0.,
+            },
+            Projection::Orthographic { width } => {
+                // The viewport is scaled to `width` and slid to the camera center, every ray
+                // goes along the view direction
+                let viewport_width = self.pixel_delta_u.len() * self.image_width as f64;
+                let scale = width / viewport_width;
+                let from_viewport_center = (s - 0.5) * self.image_width as f64 * self.pixel_delta_u
+                    + (t - 0.5) * self.image_height as f64 * self.pixel_delta_v;
+                Ray {
+                    origin: self.center + scale * from_viewport_center,
+                    direction: self.pixel_delta_u.cross(&self.pixel_delta_v).normalized(),
+                    time: 0.,
+                }
+            }
+        }
+    }
+
+    /// Random point on the camera lens disk
+    fn defocus_disk_sample(&self) -> Point {
+        let p = Vec3::random_in_unit_disk();
+        self.center + p.x * self.defocus_disk_u + p.y * self.defocus_disk_v
+    }
+
+    /// Offset from the pixel center of its `sample`-th sample, stratified: the pixel is divided
+    /// in a grid of sqrt(sample_per_pixel) cells per side, each of the first samples is jittered
+    /// in its own cell. The samples left over by the grid are spread over the whole pixel.
+    fn sample_offset(&self, sample: u32) -> Vec3 {
+        let grid = (self.sample_per_pixel as f64).sqrt() as u32;
+        if sample >= grid * grid {
+            return Camera::sample_square();
+        }
+        let jitter = Camera::sample_square();
+        let cell = |index: u32, jitter: f64| (index as f64 + 0.5 + jitter) / grid as f64 - 0.5;
+        Vec3 {
+            x: 0.,
+            y: cell(sample / grid, jitter.y),
+            z: cell(sample % grid, jitter.z),
+        }
+    }
+
+    // Returns the vector to a random point in the [-.5,-.5];[+.5,+.5] unit square.
+    fn sample_square() -> Vec3 {
+        Vec3 {
+            x: 0.,
+            y: utils::random::<f64>() - 0.5, // utils::random::<f64> output is in [0;1[
+            z: utils::random::<f64>() - 0.5,
+        }
+    }
+}
+
+/// Write the linear framebuffer of `Camera::render_hdr` as a raw dump of 16 bit half floats,
+/// keeping values above 1.
+/// The header is `F16_MAGIC` followed by the width and the height as little endian u32.
+/// The pixels follow row by row, each channel (r, g, b) as a little endian f16.
+pub fn save_f16(image: &Rgb32FImage, path: &Path) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(F16_MAGIC)?;
+    file.write_all(&image.width().to_le_bytes())?;
+    file.write_all(&image.height().to_le_bytes())?;
+    for pixel in image.pixels() {
+        for channel in pixel.0 {
+            file.write_all(&f16::from_f32(channel).to_le_bytes())?;
+        }
+    }
+    file.flush()
+}
+
+/// Write the image as a portable pixmap (https://en.wikipedia.org/wiki/Netpbm#Description).
+/// P3 files hold the channel values written in ASCII, P6 files hold them as raw bytes.
+pub fn save_ppm(image: &RgbImage, path: &Path, ascii: bool) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let magic = if ascii { "P3" } else { "P6" };
+    writeln!(
+        file,
+        "{magic}\n{} {}\n{MAX_COLOR_CHANNEL_VALUE}",
+        image.width(),
+        image.height()
+    )?;
+    if ascii {
+        for row in image.rows() {
+            let line: Vec<String> = row
+                .flat_map(|pixel| pixel.0)
+                .map(|channel| channel.to_string())
+                .collect();
+            writeln!(file, "{}", line.join(" "))?;
+        }
+    } else {
+        file.write_all(image.as_raw())?;
+    }
+    file.flush()
+}
+
+/// Write a floating point image as OpenEXR, keeping values above 1.
+pub fn save_exr(image: &Rgb32FImage, path: &Path) -> ImageResult<()> {
+    image.save_with_format(path, ImageFormat::OpenExr)
+}
+
+/// Fit the image inside an output of the given dimensions, keeping its proportions.
+/// The image is scaled to touch the borders of the output on one axis and centered on the
+/// other one, the margins are filled with `bar_color`.
+/// Panics if the image or the output has no pixels.
+pub fn letterbox(image: &RgbImage, out_width: u32, out_height: u32, bar_color: Color) -> RgbImage {
+    assert!(
+        out_width > 0 && out_height > 0,
+        "Can't letterbox into an output of {out_width}x{out_height} pixels"
+    );
+    assert!(
+        image.width() > 0 && image.height() > 0,
+        "Can't letterbox an empty image"
+    );
+    let scale = f64::min(
+        out_width as f64 / image.width() as f64,
+        out_height as f64 / image.height() as f64,
+    );
+    let width = ((image.width() as f64 * scale).round() as u32).clamp(1, out_width);
+    let height = ((image.height() as f64 * scale).round() as u32).clamp(1, out_height);
+    let scaled = imageops::resize(image, width, height, FilterType::Triangle);
+
+    let mut output = RgbImage::from_pixel(out_width, out_height, bar_color.into());
+    imageops::replace(
+        &mut output,
+        &scaled,
+        ((out_width - width) / 2) as i64,
+        ((out_height - height) / 2) as i64,
+    );
+    output
+}
+
+/// Save the image letterboxed into an output of the given dimensions, see `letterbox`.
+/// Fails with a dimension mismatch if the image or the output has no pixels.
+pub fn save_letterboxed(
+    image: &RgbImage,
+    path: &Path,
+    out_width: u32,
+    out_height: u32,
+    bar_color: Color,
+) -> ImageResult<()> {
+    if out_width == 0 || out_height == 0 || image.width() == 0 || image.height() == 0 {
+        return Err(ImageError::Parameter(ParameterError::from_kind(
+            ParameterErrorKind::DimensionMismatch,
+        )));
+    }
+    letterbox(image, out_width, out_height, bar_color).save(path)
+}
+
+/// Settings of `denoise_guided`. A sigma of 0 only averages the neighbors that are equal to
+/// the pixel in that respect.
+pub struct DenoiseParams {
+    /// Half size of the square window of neighbors averaged for each pixel
+    pub radius: u32,
+    /// Standard deviation of the spatial weight, in pixels
+    pub sigma_spatial: f64,
+    /// Standard deviation of the weight on the difference of normals, in [0,1] channel units
+    pub sigma_normal: f64,
+    /// Standard deviation of the weight on the difference of albedos, in [0,1] channel units
+    pub sigma_albedo: f64,
+}
+
+/// Squared distance between two pixels, with channels normalized in [0,1]
+fn pixel_distance_squared(a: &Rgb<u8>, b: &Rgb<u8>) -> f64 {
+    a.0.iter()
+        .zip(b.0)
+        .map(|(a, b)| (*a as f64 - b as f64) / MAX_COLOR_CHANNEL_VALUE as f64)
+        .map(|d| d * d)
+        .sum()
+}
+
+/// Denoise the rendered image with a joint bilateral filter.
+/// Each pixel is replaced by the weighted mean of its neighbors. Neighbors are weighted by their
+/// distance and by how close their normal and albedo are to the ones of the pixel, so that
+/// geometric and texture edges are preserved while flat regions are smoothed.
+/// The three images must have the same dimensions.
+pub fn denoise_guided(
+    beauty: &RgbImage,
+    normals: &RgbImage,
+    albedo: &RgbImage,
+    params: &DenoiseParams,
+) -> RgbImage {
+    let (width, height) = beauty.dimensions();
+    let radius = params.radius as i64;
+    let gaussian = |distance_squared: f64, sigma: f64| {
+        // A null sigma would give 0 / 0 for equal pixels, a tiny one keeps only them
+        let sigma = sigma.max(1e-6);
+        (-distance_squared / (2.0 * sigma * sigma)).exp()
+    };
+
+    RgbImage::from_fn(width, height, |x, y| {
+        let normal = normals.get_pixel(x, y);
+        let base_albedo = albedo.get_pixel(x, y);
+        let mut sum = [0.0; 3];
+        let mut total_weight = 0.0;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                    continue;
+                }
+                let (nx, ny) = (nx as u32, ny as u32);
+                let weight = gaussian((dx * dx + dy * dy) as f64, params.sigma_spatial)
+                    * gaussian(
+                        pixel_distance_squared(normal, normals.get_pixel(nx, ny)),
+                        params.sigma_normal,
+                    )
+                    * gaussian(
+                        pixel_distance_squared(base_albedo, albedo.get_pixel(nx, ny)),
+                        params.sigma_albedo,
+                    );
+                for (sum, channel) in sum.iter_mut().zip(beauty.get_pixel(nx, ny).0) {
+                    *sum += weight * channel as f64;
+                }
+                total_weight += weight;
+            }
+        }
+        // The pixel itself always has a weight of 1, total_weight can't be 0
+        Rgb(sum.map(|channel| (channel / total_weight).round() as u8))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{
+        AreaLight, Dielectric, DiffuseLight, Hittable, Lambertian, Material, Metal, Quad,
+    };
+    use crate::scenes;
+    use crate::texture::Texture;
+
+    /// Diffuse material of a solid color
+    fn lambertian(color: Color) -> Arc<Material> {
+        Arc::new(Material {
+            material_type: MaterialType::Lambertian(Lambertian),
+            albedo: Texture::Solid(color),
+        })
+    }
+
+    #[test]
+    fn seeded_renders_are_reproducible() {
+        let world = scenes::three_close_spheres().0;
+        let lookfrom = Point::new(-1.5, 1.5, 0.);
+        let lookat = Point::new(1.2, 0., 0.);
+        let vup = Vec3::new(0., 1., 0.);
+        let camera = Camera::look_at(1.5, 24, 4, 10, 90.0, 2.0, 3.0, lookfrom, lookat, vup);
+        let render = |seed: u64| camera.clone().with_seed(seed).render(&world, true);
+        assert_eq!(render(42), render(42));
+        assert_ne!(render(42), render(43));
+    }
+
+    #[test]
+    fn stratified_samples_one_per_cell() {
+        let camera = Camera::init(1.0, 1, 18, 1, 90.0);
+        // 4x4 grid, the two samples left are anywhere in the pixel
+        let mut cells = vec![0; 16];
+        for sample in 0..16 {
+            let offset = camera.sample_offset(sample);
+            assert!(offset.y.abs() <= 0.5 && offset.z.abs() <= 0.5);
+            let cell = |coordinate: f64| ((coordinate + 0.5) * 4.) as usize;
+            cells[cell(offset.y) * 4 + cell(offset.z)] += 1;
+        }
+        assert_eq!(cells, vec![1; 16]);
+        for sample in 16..18 {
+            let offset = camera.sample_offset(sample);
+            assert!(offset.y.abs() <= 0.5 && offset.z.abs() <= 0.5);
+        }
+    }
+
+    #[test]
+    fn render_progress_per_row() {
+        let camera = Camera::init(2.0, 8, 1, 2, 90.0);
+        let world = World::new();
+        let mut calls = Vec::new();
+        let image =
+            camera.render_with_progress(&world, false, |done, total| calls.push((done, total)));
+        assert_eq!(calls.len(), image.height() as usize);
+        assert_eq!(calls, (1..=4).map(|done| (done, 4)).collect::<Vec<_>>());
+    }
+
+    /// Pixel of a 1x1 render of an empty world, seeing only a background of the given color
+    fn background_pixel(color: Color, samples: u32, gamma_corrected: bool) -> Color {
+        let camera =
+            Camera::init(1.0, 1, samples, 1, 90.0).with_background(Background::Solid(color));
+        let pixel = camera
+            .render(&World::new(), gamma_corrected)
+            .get_pixel(0, 0)
+            .0;
+        Color::from(pixel)
+    }
+
+    #[test]
+    fn mean_color_of_many_samples() {
+        assert_eq!(
+            background_pixel(Color::white(), 1000, false),
+            Color::white()
+        );
+
+        // Rounded rather than truncated toward black
+        let colors = [Color { r: 1, g: 0, b: 10 }, Color { r: 2, g: 1, b: 11 }];
+        let mean = (Color3::from(colors[0]) + Color3::from(colors[1])) / 2.;
+        assert_eq!(mean.to_color(), Color { r: 2, g: 1, b: 11 });
+    }
+
+    #[test]
+    fn color3_accumulation_does_not_clamp() {
+        let mut sum = Color3::black();
+        for _ in 0..1000 {
+            sum += Color3::from(Color::white()) * 2.;
+        }
+        assert_eq!(sum.r, 2000.);
+        // Only clamped when displayed
+        assert_eq!((sum / 1000.).r, 2.);
+        assert_eq!((sum / 1000.).to_color(), Color::white());
+        assert_eq!(
+            (sum / 4000.).to_color(),
+            Color {
+                r: 128,
+                g: 128,
+                b: 128
+            }
+        );
+    }
+
+    #[test]
+    fn gamma_correction_brightens_mid_tones() {
+        let gray = |value: u8| Color {
+            r: value,
+            g: value,
+            b: value,
+        };
+        // Linear 0.25 is 0.5 in gamma 2 space
+        assert_eq!(background_pixel(gray(64), 4, true), gray(128));
+        // Extremes are left unchanged
+        assert_eq!(background_pixel(gray(0), 4, true), gray(0));
+        assert_eq!(background_pixel(gray(255), 4, true), gray(255));
+        // Averaged in linear space first
+        let mean = (Color3::from(gray(0)) + Color3::from(gray(128))) / 2.;
+        assert_eq!(mean.gamma_corrected().to_color(), gray(128));
+    }
+
+    #[test]
+    fn color_lerp() {
+        let (black, white) = (Color::black(), Color::white());
+        assert_eq!(
+            Color::lerp(black, white, 0.5),
+            Color {
+                r: 128,
+                g: 128,
+                b: 128
+            }
+        );
+        assert_eq!(Color::lerp(black, white, 0.), black);
+        assert_eq!(Color::lerp(black, white, 1.), white);
+        let sky = Color {
+            r: 127,
+            g: 178,
+            b: 255,
+        };
+        assert_eq!(Color::lerp(white, sky, 1.), sky);
+    }
+
+    #[test]
+    fn color_mul_f64() {
+        let color = Color {
+            r: 100,
             g: 40,
             b: 80,
         };
@@ -292,6 +2016,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn color_from_f64_channel_order() {
+        let color = Color::from([0.1, 0.2, 0.3]);
+        assert!(color.r < color.g && color.g < color.b);
+        assert_eq!(Color::from([0.0, 1.0, 0.0]), Color { r: 0, g: 255, b: 0 });
+    }
+
     #[test]
     fn color_add() {
         let color1 = Color {
@@ -354,4 +2085,1332 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn color_mul_saturates() {
+        let color = Color {
+            r: 200,
+            g: 128,
+            b: 0,
+        };
+        assert_eq!(
+            color * 2.0,
+            Color {
+                r: 255,
+                g: 255,
+                b: 0
+            }
+        );
+        assert_eq!(
+            2.0 * color,
+            Color {
+                r: 255,
+                g: 255,
+                b: 0
+            }
+        );
+        assert_eq!(color * -1.0, Color::black());
+        assert_eq!(color * Color::white(), color);
+        let white = Color::white();
+        assert_eq!(white * white, white);
+    }
+
+    #[test]
+    fn save_f16_layout() {
+        let mut img = Rgb32FImage::new(2, 1);
+        img.put_pixel(0, 0, Rgb([0., 0.5, 1.]));
+        img.put_pixel(1, 0, Rgb([0.2, 0.4, 0.8]));
+        let values = saved_f16_values(&img, "save_f16_layout.f16");
+        assert_eq!(values.len(), 6);
+        let expected = [0.0, 0.5, 1.0, 0.2, 0.4, 0.8];
+        // Half floats have a 10 bit mantissa, values in [0.5,1] are precise to 2^-11
+        for (value, expected) in values.iter().zip(expected) {
+            assert!((value - expected).abs() <= 2.0_f64.powi(-11));
+        }
+    }
+
+    #[test]
+    fn save_f16_keeps_bright_values() {
+        let img = Rgb32FImage::from_pixel(1, 1, Rgb([1.5, 15., 1000.]));
+        let values = saved_f16_values(&img, "save_f16_keeps_bright_values.f16");
+        assert_eq!(values, [1.5, 15., 1000.]);
+    }
+
+    /// Channels of the image written by `save_f16`, after checking the header
+    fn saved_f16_values(img: &Rgb32FImage, file_name: &str) -> Vec<f64> {
+        let path = std::env::temp_dir().join(file_name);
+        save_f16(img, &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&bytes[0..4], F16_MAGIC);
+        assert_eq!(
+            u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            img.width()
+        );
+        assert_eq!(
+            u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            img.height()
+        );
+        bytes[12..]
+            .chunks(2)
+            .map(|b| f16::from_le_bytes([b[0], b[1]]).to_f64())
+            .collect()
+    }
+
+    #[test]
+    fn save_ppm_formats() {
+        let mut img = RgbImage::new(2, 2);
+        img.put_pixel(0, 0, Rgb([255, 0, 0]));
+        img.put_pixel(1, 0, Rgb([0, 255, 0]));
+        img.put_pixel(0, 1, Rgb([0, 0, 255]));
+        img.put_pixel(1, 1, Rgb([1, 2, 3]));
+
+        let path = std::env::temp_dir().join("save_ppm_formats.ppm");
+        save_ppm(&img, &path, true).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text, "P3\n2 2\n255\n255 0 0 0 255 0\n0 0 255 1 2 3\n");
+
+        save_ppm(&img, &path, false).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let header = b"P6\n2 2\n255\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(&bytes[header.len()..], img.as_raw().as_slice());
+        // The image crate reads it back
+        let decoded = image::load_from_memory(&bytes).unwrap().to_rgb8();
+        assert_eq!(decoded, img);
+    }
+
+    #[test]
+    fn render_region_matches_full_render() {
+        let world = scenes::three_close_spheres().0;
+        let camera = Camera::init(1.5, 30, 2, 5, 90.0).with_seed(7);
+        let full = camera.render(&world, false);
+        let region = camera.render_region(&world, false, 10, 5, 8, 6);
+        assert_eq!(region, imageops::crop_imm(&full, 10, 5, 8, 6).to_image());
+
+        // Cropped to the image, or empty
+        let edge = camera.render_region(&world, false, 25, 15, 10, 10);
+        assert_eq!(edge.dimensions(), (5, 5));
+        assert_eq!(edge, imageops::crop_imm(&full, 25, 15, 5, 5).to_image());
+        let empty = camera.render_region(&world, false, 3, 3, 0, 4);
+        assert_eq!(empty.dimensions(), (0, 4));
+        assert_eq!(camera.render_region(&world, false, 40, 0, 5, 5).width(), 0);
+    }
+
+    #[test]
+    fn normal_and_depth_buffers() {
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(3., 0., 0.),
+            radius: 1.,
+            material: lambertian(Color::white()),
+        }));
+        let camera = Camera::init(1.0, 9, 1, 1, 90.0);
+
+        // The sphere faces the camera (-x) in the middle, and the top (+y) near its top edge
+        let normals = camera.render_normals(&world);
+        assert_eq!(normals.get_pixel(4, 4).0, [0, 127, 127]);
+        let top = normals.get_pixel(4, 3).0;
+        assert!(top[1] > 127 && top[0] < 127);
+        assert_eq!(normals.get_pixel(0, 0).0, [0, 0, 0]);
+
+        let depth = camera.render_depth(&world, 4.);
+        // 2 away from the camera
+        assert_eq!(depth.get_pixel(4, 4).0, [127; 3]);
+        assert!(depth.get_pixel(4, 3).0[0] < 127);
+        assert_eq!(depth.get_pixel(0, 0).0, [0; 3]);
+    }
+
+    #[test]
+    fn render_rgba_alpha_coverage() {
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(1., 0., 0.),
+            radius: 0.5,
+            material: lambertian(Color::white()),
+        }));
+        let camera = Camera::init(1.0, 16, 64, 2, 90.0).with_seed(3);
+        let image = camera.render_rgba(&world, false);
+
+        assert_eq!(image.get_pixel(8, 8)[3], 255);
+        assert_eq!(image.get_pixel(0, 0)[3], 0);
+        assert_eq!(image.get_pixel(15, 8)[3], 0);
+        // The silhouette crosses the middle row, partially covering some pixels
+        assert!((0..16).any(|x| {
+            let alpha = image.get_pixel(x, 8)[3];
+            alpha > 0 && alpha < 255
+        }));
+
+        // A red light against a blue background: edge pixels stay red, with no blue mixed in
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(1., 0., 0.),
+            radius: 0.5,
+            material: Arc::new(Material {
+                material_type: MaterialType::DiffuseLight(DiffuseLight {
+                    emission: Color3 {
+                        r: 1.,
+                        g: 0.,
+                        b: 0.,
+                    },
+                }),
+                albedo: Texture::Solid(Color::black()),
+            }),
+        }));
+        let image = camera
+            .with_background(Background::Solid(Color { r: 0, g: 0, b: 255 }))
+            .render_rgba(&world, false);
+        for pixel in image.pixels() {
+            let expected = if pixel[3] > 0 { [255, 0, 0] } else { [0, 0, 0] };
+            assert_eq!(pixel.0[..3], expected);
+        }
+    }
+
+    #[test]
+    fn bounce_heatmap() {
+        let camera = Camera::init(1.0, 4, 4, 10, 90.0).with_seed(1);
+        let heatmap = camera.render_bounce_heatmap(&World::new());
+        // Every path is a single ray to the background
+        assert!(heatmap.pixels().all(|pixel| *pixel == Rgb([0, 0, 255])));
+
+        // Rays bounce inside the glass sphere in front of the camera
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(2., 0., 0.),
+            radius: 1.,
+            material: Arc::new(Material {
+                material_type: MaterialType::Dielectric(Dielectric {
+                    refraction_index: 1.5,
+                    absorption: Color3::black(),
+                }),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        }));
+        let heatmap = camera.render_bounce_heatmap(&world);
+        assert!(heatmap.get_pixel(2, 2)[0] > heatmap.get_pixel(0, 0)[0]);
+        // Seeded samples don't depend on the thread rendering them
+        assert_eq!(camera.render_bounce_heatmap(&world), heatmap);
+    }
+
+    #[test]
+    fn progressive_render_converges_to_render() {
+        let (world, _) = scenes::three_close_spheres();
+        let camera = Camera::init(1.5, 12, 4, 10, 90.0).with_seed(9);
+        let passes: Vec<RgbImage> = camera.render_progressive(&world, true).collect();
+        assert_eq!(passes.len(), 4);
+        assert_eq!(passes.last(), Some(&camera.render(&world, true)));
+    }
+
+    #[test]
+    fn render_16bit_range() {
+        let world = World::new();
+        let camera =
+            Camera::init(2.0, 4, 1, 1, 90.0).with_background(Background::Solid(Color::white()));
+        let image = camera.render_16bit(&world, true);
+        assert_eq!(image.dimensions(), (4, 2));
+        assert_eq!(image.get_pixel(0, 0).0, [u16::MAX; 3]);
+
+        let color = Color3 {
+            r: 0.5,
+            g: 0.,
+            b: 1.5,
+        };
+        assert_eq!(color.to_rgb16().0, [32768, 0, 65535]);
+
+        // Saved as a 16 bit PNG
+        let path = std::env::temp_dir().join("render_16bit_range.png");
+        image.save(&path).unwrap();
+        let decoded = image::open(&path).unwrap().into_rgb16();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn reinhard_tone_map() {
+        let bright = Color3 {
+            r: 100.,
+            g: 1.,
+            b: 0.,
+        };
+        let mapped = ToneMap::Reinhard.apply(bright);
+        assert!(mapped.r < 1. && mapped.r > 0.99);
+        assert_eq!(mapped.g, 0.5);
+        assert_eq!(mapped.b, 0.);
+        assert_eq!(ToneMap::None.apply(bright), bright);
+
+        // Applied to the rendered pixels, before quantization
+        let world = World::new();
+        let gray = Color {
+            r: 128,
+            g: 128,
+            b: 128,
+        };
+        let camera = Camera::init(1.0, 1, 1, 1, 90.0)
+            .with_background(Background::Solid(gray))
+            .with_tone_map(ToneMap::Reinhard);
+        // 0.502 / 1.502
+        assert_eq!(camera.render(&world, false).get_pixel(0, 0).0, [85, 85, 85]);
+    }
+
+    #[test]
+    fn render_hdr_saved_as_exr() {
+        let world = World::new();
+        let sky = Color {
+            r: 51,
+            g: 102,
+            b: 255,
+        };
+        let camera = Camera::init(2.0, 4, 2, 1, 90.0).with_background(Background::Solid(sky));
+        let mut image = camera.render_hdr(&world);
+        assert_eq!(image.dimensions(), (4, 2));
+        assert_eq!(image.get_pixel(1, 1).0, [0.2, 0.4, 1.0]);
+
+        // Values beyond the displayable range survive the round trip
+        image.put_pixel(0, 0, Rgb([3.5, 0., 12.25]));
+        let path = std::env::temp_dir().join("render_hdr_saved_as_exr.exr");
+        save_exr(&image, &path).unwrap();
+        let decoded = image::open(&path).unwrap().into_rgb32f();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn render_tiles_rectangles() {
+        let world = World::new();
+        let camera = Camera::init(1.0, 64, 1, 1, 90.0);
+        let mut tiles = Vec::new();
+        camera.render_tiles(&world, false, 32, BucketOrder::TopDown, |tile| {
+            assert_eq!(tile.pixels.dimensions(), (tile.width, tile.height));
+            tiles.push((tile.x, tile.y, tile.width, tile.height));
+        });
+        assert_eq!(
+            tiles,
+            vec![
+                (0, 0, 32, 32),
+                (32, 0, 32, 32),
+                (0, 32, 32, 32),
+                (32, 32, 32, 32)
+            ]
+        );
+    }
+
+    #[test]
+    fn render_tiles_cropped_on_edges() {
+        let world = World::new();
+        let camera = Camera::init(2.0, 40, 1, 1, 90.0);
+        let mut tiles = Vec::new();
+        camera.render_tiles(&world, false, 32, BucketOrder::TopDown, |tile| {
+            tiles.push((tile.x, tile.y, tile.width, tile.height))
+        });
+        assert_eq!(tiles, vec![(0, 0, 32, 20), (32, 0, 8, 20)]);
+    }
+
+    #[test]
+    fn render_tiles_of_size_zero() {
+        let world = World::new();
+        let camera = Camera::init(1.0, 2, 1, 1, 90.0);
+        for order in [
+            BucketOrder::TopDown,
+            BucketOrder::Spiral,
+            BucketOrder::Hilbert,
+        ] {
+            let mut tiles = Vec::new();
+            camera.render_tiles(&world, false, 0, order, |tile| {
+                tiles.push((tile.x, tile.y, tile.width, tile.height))
+            });
+            tiles.sort();
+            assert_eq!(
+                tiles,
+                vec![(0, 0, 1, 1), (0, 1, 1, 1), (1, 0, 1, 1), (1, 1, 1, 1)]
+            );
+        }
+    }
+
+    #[test]
+    fn letterbox_wide_render_in_square_output() {
+        let red = Color { r: 255, g: 0, b: 0 };
+        let bar_color = Color { r: 0, g: 0, b: 255 };
+        let render = RgbImage::from_pixel(8, 4, red.into());
+        let output = letterbox(&render, 8, 8, bar_color);
+        assert_eq!(output.dimensions(), (8, 8));
+        for (_, y, pixel) in output.enumerate_pixels() {
+            // The render is centered, with 2 rows of bars above and below
+            if (2..6).contains(&y) {
+                assert_eq!(*pixel, red.into());
+            } else {
+                assert_eq!(*pixel, bar_color.into());
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "output of 0x8 pixels")]
+    fn letterbox_zero_width_output() {
+        let render = RgbImage::new(8, 4);
+        letterbox(&render, 0, 8, Color::black());
+    }
+
+    #[test]
+    fn save_letterboxed_zero_width_output() {
+        let path = std::env::temp_dir().join("save_letterboxed_zero_width_output.png");
+        let render = RgbImage::new(8, 4);
+        let result = save_letterboxed(&render, &path, 0, 8, Color::black());
+        assert!(matches!(
+            result,
+            Err(ImageError::Parameter(ref error))
+                if error.kind() == ParameterErrorKind::DimensionMismatch
+        ));
+        assert!(!path.exists());
+
+        let empty = RgbImage::new(0, 0);
+        assert!(save_letterboxed(&empty, &path, 8, 8, Color::black()).is_err());
+    }
+
+    #[test]
+    fn camera_look_at_orientation() {
+        let lookfrom = Point::new(1., 2., 3.);
+        let lookat = Point::new(1., 2., 0.);
+        let vup = Vec3::new(0., 1., 0.);
+        let camera = Camera::look_at(1.0, 11, 1, 1, 90.0, 0., 3., lookfrom, lookat, vup);
+        assert_eq!(camera.center, lookfrom);
+        // The center of the middle pixel is the point the camera looks at
+        let middle = camera.pixel_00_loc + 5.0 * camera.pixel_delta_u + 5.0 * camera.pixel_delta_v;
+        assert!((middle - lookat).len() < 1e-9);
+        // Looking toward -z, the right of the image is +x and its bottom is -y
+        assert!(camera.pixel_delta_u.x > 0.);
+        assert!(camera.pixel_delta_u.y.abs() < 1e-9 && camera.pixel_delta_u.z.abs() < 1e-9);
+        assert!(camera.pixel_delta_v.y < 0.);
+        assert!(camera.pixel_delta_v.x.abs() < 1e-9 && camera.pixel_delta_v.z.abs() < 1e-9);
+    }
+
+    #[test]
+    fn camera_vertical_fov() {
+        // At 90 degrees, the viewport is as high as twice the focal length
+        let wide = Camera::init(1.0, 10, 1, 1, 90.0);
+        assert!((10.0 * wide.pixel_delta_v.len() - 2.0).abs() < 1e-9);
+        // Narrowing the field of view zooms in, the viewport shrinks
+        let telephoto = Camera::init(1.0, 10, 1, 1, 20.0);
+        let expected_height = 2.0 * 10.0_f64.to_radians().tan();
+        assert!((10.0 * telephoto.pixel_delta_v.len() - expected_height).abs() < 1e-9);
+    }
+
+    #[test]
+    fn render_with_identity_ray_fn() {
+        let world = scenes::three_close_spheres().0;
+        // Single bounce: rays hitting an object are black, the others get the sky color
+        let camera = Camera::init(1.0, 32, 4, 1, 90.0).with_seed(6);
+        let pinhole = camera.render(&world, false);
+        let custom = camera.render_with_ray_fn(&world, false, |s, t| camera.ray_through(s, t));
+        assert_eq!(custom, pinhole);
+    }
+
+    #[test]
+    fn camera_defocus() {
+        let lookfrom = Point::new(0., 0., 0.);
+        let lookat = Point::new(1., 0., 0.);
+        let vup = Vec3::new(0., 1., 0.);
+        let pinhole = Camera::look_at(1.0, 11, 1, 1, 90.0, 0., 4., lookfrom, lookat, vup);
+        for _ in 0..100 {
+            assert_eq!(pinhole.ray_through(0.5, 0.5).origin, lookfrom);
+        }
+
+        let focus_dist = 4.;
+        let defocused =
+            Camera::look_at(1.0, 11, 1, 1, 90.0, 10., focus_dist, lookfrom, lookat, vup);
+        let lens_radius = focus_dist * 5.0_f64.to_radians().tan();
+        let focus_point = Point::new(focus_dist, 0., 0.);
+        let mut origins = Vec::new();
+        for _ in 0..100 {
+            let ray = defocused.ray_through(0.5, 0.5);
+            // The lens disk faces the view direction
+            assert_eq!(ray.origin.x, 0.);
+            assert!(ray.origin.len() <= lens_radius);
+            // Rays through the same point of the image meet on the plane of focus
+            assert!((ray.origin + ray.direction - focus_point).len() < 1e-9);
+            origins.push(ray.origin);
+        }
+        assert!(origins.iter().any(|origin| *origin != lookfrom));
+    }
+
+    /// Horizontal gradient with a stop per pixel, the channels of the stop t being
+    /// [t, t / 2, 1 - t] in linear space. Each channel varies differently so that swapped
+    /// channels are caught.
+    fn linear_gradient(stops: &[f64]) -> RgbImage {
+        let mut img = RgbImage::new(stops.len() as u32, 1);
+        for (x, t) in stops.iter().enumerate() {
+            let color = Color::from([*t, t / 2.0, 1.0 - t]);
+            img.put_pixel(x as u32, 0, color.into());
+        }
+        img
+    }
+
+    #[test]
+    fn linear_gradient_encoding() {
+        // Stops outside of [0,1] check the clamping
+        let stops = [-0.25, 0.0, 0.25, 0.5, 0.75, 1.0, 1.25];
+        // Hand computed, channel * 255 truncated
+        let expected = [
+            [0, 0, 255],
+            [0, 0, 255],
+            [63, 31, 191],
+            [127, 63, 127],
+            [191, 95, 63],
+            [255, 127, 0],
+            [255, 159, 0],
+        ];
+        let gradient = linear_gradient(&stops);
+        for (x, expected) in expected.iter().enumerate() {
+            assert_eq!(
+                gradient.get_pixel(x as u32, 0).0,
+                *expected,
+                "stop {}",
+                stops[x]
+            );
+        }
+
+        // Hand computed, channel * 255 rounded: linear value, then without gamma, with the
+        // gamma 2 approximation and with the sRGB curve
+        let checkpoints = [
+            (-0.25, 0, 0, 0),
+            (0.0, 0, 0, 0),
+            (0.002, 1, 11, 7),
+            (0.01, 3, 26, 25),
+            (0.18, 46, 108, 118),
+            (0.5, 128, 180, 188),
+            (0.75, 191, 221, 225),
+            (1.0, 255, 255, 255),
+            (1.25, 255, 255, 255),
+        ];
+        for (linear, raw, gamma_2, srgb) in checkpoints {
+            // Channels get different values to catch swapped channels
+            let color = Color3 {
+                r: linear,
+                g: 0.,
+                b: 1.,
+            };
+            assert_eq!(
+                color.to_color(),
+                Color {
+                    r: raw,
+                    g: 0,
+                    b: 255
+                },
+                "{linear}"
+            );
+            assert_eq!(color.gamma_corrected().to_color().r, gamma_2, "{linear}");
+            assert_eq!(
+                color.srgb_encoded().to_color(),
+                Color {
+                    r: srgb,
+                    g: 0,
+                    b: 255
+                },
+                "{linear}"
+            );
+        }
+    }
+
+    #[test]
+    fn parallel_render_matches_scene() {
+        let world = scenes::three_close_spheres().0;
+        // Single bounce: rays hitting an object are black, the others get the sky color
+        let camera = Camera::init(1.0, 32, 1, 1, 90.0);
+        let img = camera.render(&world, false);
+        assert_eq!(img.dimensions(), (32, 32));
+        // The center sphere is in the middle of the image, the sky at the top
+        assert_eq!(img.get_pixel(16, 16).0, [0, 0, 0]);
+        assert_ne!(img.get_pixel(16, 0).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn denoise_guided_preserves_normal_edges() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        // Two faces of a cube meeting at x = 8, with a noisy lighting on each face
+        let mut rng = StdRng::seed_from_u64(3);
+        let beauty = RgbImage::from_fn(16, 16, |x, _| {
+            let base: i32 = if x < 8 { 60 } else { 190 };
+            Rgb([(base + rng.gen_range(-40..=40)) as u8; 3])
+        });
+        let normals = RgbImage::from_fn(16, 16, |x, _| {
+            if x < 8 {
+                Rgb([255, 128, 128])
+            } else {
+                Rgb([128, 255, 128])
+            }
+        });
+        let albedo = RgbImage::from_pixel(16, 16, Rgb([200, 200, 200]));
+        let params = DenoiseParams {
+            radius: 3,
+            sigma_spatial: 2.0,
+            sigma_normal: 0.1,
+            sigma_albedo: 0.1,
+        };
+        let denoised = denoise_guided(&beauty, &normals, &albedo, &params);
+
+        let face_variance = |img: &RgbImage, faces: std::ops::Range<u32>| {
+            let values: Vec<f64> = img
+                .enumerate_pixels()
+                .filter(|(x, _, _)| faces.contains(x))
+                .map(|(_, _, p)| p.0[0] as f64)
+                .collect();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64
+        };
+        // Noise is smoothed on the flat faces
+        assert!(face_variance(&denoised, 0..8) < face_variance(&beauty, 0..8) / 4.0);
+        assert!(face_variance(&denoised, 8..16) < face_variance(&beauty, 8..16) / 4.0);
+        // The edge between the faces stays sharp
+        for y in 0..16 {
+            assert!((denoised.get_pixel(7, y).0[0] as i32 - 60).abs() < 25);
+            assert!((denoised.get_pixel(8, y).0[0] as i32 - 190).abs() < 25);
+        }
+    }
+
+    #[test]
+    fn denoise_guided_null_sigmas() {
+        let beauty = RgbImage::from_fn(4, 4, |x, y| Rgb([(x * 60) as u8, (y * 60) as u8, 7]));
+        let normals = RgbImage::from_pixel(4, 4, Rgb([128, 128, 255]));
+        let albedo = RgbImage::from_pixel(4, 4, Rgb([200, 200, 200]));
+        let params = DenoiseParams {
+            radius: 2,
+            sigma_spatial: 0.,
+            sigma_normal: 0.,
+            sigma_albedo: 0.,
+        };
+        // Only the pixel itself is left in its window
+        assert_eq!(denoise_guided(&beauty, &normals, &albedo, &params), beauty);
+    }
+
+    fn assert_covers_grid(sequence: &[(u32, u32)], columns: u32, rows: u32) {
+        let mut sorted = sequence.to_vec();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), sequence.len(), "tiles visited more than once");
+        assert_eq!(sorted.len(), (columns * rows) as usize);
+        assert!(sorted.iter().all(|(c, r)| *c < columns && *r < rows));
+    }
+
+    #[test]
+    fn bucket_order_empty_grid() {
+        for order in [
+            BucketOrder::TopDown,
+            BucketOrder::Spiral,
+            BucketOrder::Hilbert,
+        ] {
+            assert_eq!(order.sequence(0, 3), vec![]);
+            assert_eq!(order.sequence(3, 0), vec![]);
+        }
+    }
+
+    #[test]
+    fn bucket_order_spiral() {
+        let sequence = BucketOrder::Spiral.sequence(5, 3);
+        assert_eq!(sequence[0], (2, 1));
+        assert_eq!(sequence[1], (3, 1));
+        assert_covers_grid(&sequence, 5, 3);
+    }
+
+    #[test]
+    fn bucket_order_hilbert() {
+        for (columns, rows) in [(4, 4), (5, 3), (1, 1), (7, 2)] {
+            let sequence = BucketOrder::Hilbert.sequence(columns, rows);
+            assert_covers_grid(&sequence, columns, rows);
+        }
+        // On a full square grid, consecutive tiles are neighbors
+        let sequence = BucketOrder::Hilbert.sequence(8, 8);
+        for pair in sequence.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!(a.0.abs_diff(b.0) + a.1.abs_diff(b.1), 1);
+        }
+    }
+
+    #[test]
+    fn render_tiles_bucket_order() {
+        let world = World::new();
+        let camera = Camera::init(1.0, 64, 1, 1, 90.0);
+        let mut tiles = Vec::new();
+        camera.render_tiles(&world, false, 16, BucketOrder::Spiral, |tile| {
+            tiles.push((tile.x / 16, tile.y / 16))
+        });
+        assert_eq!(tiles, BucketOrder::Spiral.sequence(4, 4));
+        assert_eq!(tiles[0], (1, 1));
+    }
+
+    #[test]
+    fn viewport_aspect_ratio() {
+        let camera = Camera::init(3.0 / 2.0, 600, 1, 1, 90.0);
+        assert_eq!(camera.image_height, 400);
+        let viewport_width = camera.pixel_delta_u.len() * camera.image_width as f64;
+        let viewport_height = camera.pixel_delta_v.len() * camera.image_height as f64;
+        assert!((viewport_width / viewport_height - 1.5).abs() < 1e-9);
+
+        // The ratio isn't rounded, pixels stay square when the height is truncated
+        let camera = Camera::init(3.0 / 2.0, 500, 1, 1, 90.0);
+        assert_eq!(camera.image_height, 333);
+        assert!((camera.pixel_delta_u.len() - camera.pixel_delta_v.len()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn stereo_eyes() {
+        let lookfrom = Point::new(0., 0., 0.);
+        let lookat = Point::new(1., 0., 0.);
+        let vup = Vec3::new(0., 1., 0.);
+        let camera = Camera::look_at(1.0, 16, 1, 1, 90.0, 0., 1., lookfrom, lookat, vup);
+        let ipd = 0.064;
+        let (left, right) = camera.stereo_eyes(ipd);
+        // Looking toward +x, the right axis of the camera is +z
+        let separation = right.center - left.center;
+        assert!((separation.z - ipd).abs() < 1e-12);
+        assert!(separation.x.abs() < 1e-12 && separation.y.abs() < 1e-12);
+        assert!((left.center.z + ipd / 2.).abs() < 1e-12);
+
+        // A close object is seen from different angles
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(0.5, 0., 0.),
+            radius: 0.1,
+            material: lambertian(Color::white()),
+        }));
+        let (left_img, right_img) = camera.render_stereo(&world, false, ipd);
+        assert_ne!(left_img, right_img);
+    }
+
+    #[test]
+    fn ray_color_lit_by_emissive_sphere() {
+        let material = |material_type| {
+            Arc::new(Material {
+                material_type,
+                albedo: Texture::Solid(Color::white()),
+            })
+        };
+        // Brighter than white, lights aren't clamped
+        let emission = Color3 {
+            r: 4.,
+            g: 2.,
+            b: 0.5,
+        };
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(3., 0., 0.),
+            radius: 1.,
+            material: material(MaterialType::DiffuseLight(DiffuseLight { emission })),
+        }));
+        let ray = Ray {
+            origin: Point::new(0., 0., 0.),
+            direction: Vec3::new(1., 0., 0.),
+            time: 0.,
+        };
+        // Lights aren't affected by the remaining bounces
+        let camera = Camera::init(1.0, 1, 1, 1, 90.0);
+        assert_eq!(camera.ray_color(&ray, &world, 1), emission);
+        assert_eq!(camera.ray_color(&ray, &world, 50), emission);
+    }
+
+    #[test]
+    fn camera_background() {
+        let world = World::new();
+        let ray = Ray {
+            origin: Point::new(0., 0., 0.),
+            direction: Vec3::new(1., 1., 0.),
+            time: 0.,
+        };
+        let camera = Camera::init(1.0, 1, 1, 1, 90.0);
+        assert_eq!(
+            camera.ray_color(&ray, &world, 1).to_color(),
+            Ray::blue_lerp(&ray)
+        );
+        let camera = camera.with_background(Background::Solid(Color::black()));
+        assert_eq!(camera.ray_color(&ray, &world, 1), Color3::black());
+    }
+
+    #[test]
+    fn gradient_background() {
+        let background = Background::Gradient {
+            top: Color::white(),
+            bottom: Color::black(),
+        };
+        let ray = |y| Ray {
+            origin: Point::new(0., 0., 0.),
+            direction: Vec3::new(0., y, 0.),
+            time: 0.,
+        };
+        assert_eq!(background.color(&ray(-1.)), Color::black());
+        assert_eq!(background.color(&ray(1.)), Color::white());
+    }
+
+    #[test]
+    fn scattered_rays_are_averaged() {
+        let camera = Camera::init(1.0, 1, 1, 2, 90.0).with_background(Background::Gradient {
+            top: Color::white(),
+            bottom: Color::black(),
+        });
+        let scattered_ray = |y| ScatteredRay {
+            ray: Ray {
+                origin: Point::new(0., 0., 0.),
+                direction: Vec3::new(0., y, 0.),
+                time: 0.,
+            },
+            attenuation: Color3::white(),
+        };
+        let world = World::new();
+        let up_and_down = [scattered_ray(1.), scattered_ray(-1.)];
+        let color = camera.scattered_color(&up_and_down, &world, 2, None);
+        assert_eq!(
+            color,
+            Color3 {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5
+            }
+        );
+        assert_eq!(
+            camera.scattered_color(&[], &world, 2, None),
+            Color3::black()
+        );
+    }
+
+    #[test]
+    fn material_override() {
+        let (world, camera) = scenes::three_close_spheres();
+        let clay = Color {
+            r: 180,
+            g: 160,
+            b: 140,
+        };
+        let camera = camera.with_material_override(lambertian(clay));
+        let mut hits = 0;
+        for x in (0..camera.image_width).step_by(10) {
+            let ray = camera.ray_through(x as f64 / camera.image_width as f64, 0.5);
+            if let Some(hit) = camera.shaded_hit(&ray, &world) {
+                hits += 1;
+                for scattered_ray in ScatteredRay::scatter(&hit, &ray) {
+                    assert_eq!(scattered_ray.attenuation, Color3::from(clay));
+                }
+            }
+        }
+        assert!(hits > 0);
+
+        // Lights keep shining
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(2., 0., 0.),
+            radius: 1.,
+            material: Arc::new(Material {
+                material_type: MaterialType::DiffuseLight(DiffuseLight {
+                    emission: Color3::white(),
+                }),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        }));
+        let hit = camera
+            .shaded_hit(&camera.ray_through(0.5, 0.5), &world)
+            .unwrap();
+        assert!(matches!(
+            hit.material.material_type,
+            MaterialType::DiffuseLight(_)
+        ));
+    }
+
+    #[test]
+    fn fog_grows_with_distance() {
+        let fog = Fog {
+            color: Color::black(),
+            density: 0.2,
+        };
+        let camera = Camera::init(1.0, 1, 1, 5, 90.0).with_fog(fog);
+        let light_at = |x| {
+            let mut world = World::new();
+            world.add(Hittable::Sphere(Sphere {
+                center: Point::new(x, 0., 0.),
+                radius: 0.5,
+                material: Arc::new(Material {
+                    material_type: MaterialType::DiffuseLight(DiffuseLight {
+                        emission: Color3::white(),
+                    }),
+                    albedo: Texture::Solid(Color::white()),
+                }),
+            }));
+            camera.ray_color(&camera.ray_through(0.5, 0.5), &world, 5).r
+        };
+        let near = light_at(2.);
+        let far = light_at(10.);
+        assert!((near - (-0.2 * 1.5_f64).exp()).abs() < 1e-9);
+        assert!((far - (-0.2 * 9.5_f64).exp()).abs() < 1e-9);
+        // The background stays clear
+        assert_eq!(
+            camera.ray_color(&camera.ray_through(0.5, 0.5), &World::new(), 5),
+            Color3::from(Ray::blue_lerp(&camera.ray_through(0.5, 0.5)))
+        );
+    }
+
+    #[test]
+    fn preview_samples_pixel_centers() {
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(2., 0., 0.),
+            radius: 1.,
+            material: Arc::new(Material {
+                material_type: MaterialType::DiffuseLight(DiffuseLight {
+                    emission: Color3::white(),
+                }),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        }));
+        let camera = Camera::init(1.0, 16, 16, 5, 90.0)
+            .with_background(Background::Solid(Color::black()))
+            .with_seed(2);
+        let preview = camera.render_preview(&world, false);
+        for (x, y, pixel) in preview.enumerate_pixels() {
+            let expected = match camera.primary_hit(&world, x, y) {
+                Some(_) => Color::white(),
+                None => Color::black(),
+            };
+            assert_eq!(*pixel, expected.into());
+        }
+        // The antialiased render blends the edges
+        let render = camera.render(&world, false);
+        assert!(render
+            .pixels()
+            .any(|pixel| *pixel != Color::white().into() && *pixel != Color::black().into()));
+    }
+
+    #[test]
+    fn render_stats_count_rays() {
+        let camera = Camera::init(2.0, 8, 3, 10, 90.0).with_seed(5);
+        let empty = World::new();
+        let (_, stats) = camera.render_with_stats(&empty, false);
+        assert_eq!(stats.primary_rays, 8 * 4 * 3);
+        // Nothing to bounce off
+        assert_eq!(stats.total_rays, stats.primary_rays);
+
+        let world = scenes::three_close_spheres().0;
+        let (image, stats) = camera.render_with_stats(&world, false);
+        assert_eq!(stats.primary_rays, 8 * 4 * 3);
+        assert!(stats.total_rays > stats.primary_rays);
+        assert_eq!(image, camera.render(&world, false));
+
+        // Adaptive sampling takes fewer samples on the flat background
+        let adaptive = camera.with_adaptive_sampling(AdaptiveSampling {
+            min_samples: 2,
+            max_variance: 1e-3,
+        });
+        let (_, stats) = adaptive.render_with_stats(&empty, false);
+        assert_eq!(stats.primary_rays, 8 * 4 * 2);
+    }
+
+    #[test]
+    fn render_with_threads_matches_default() {
+        let camera = Camera::init(2.0, 8, 2, 5, 90.0).with_seed(11);
+        let world = scenes::three_close_spheres().0;
+        assert_eq!(
+            camera.render_with_threads(&world, false, 1).unwrap(),
+            camera.render(&world, false)
+        );
+        // Pixels are seeded from their coordinates, whatever thread renders them
+        assert_eq!(
+            camera.render_with_threads(&world, false, 1).unwrap(),
+            camera.render_with_threads(&world, false, 8).unwrap()
+        );
+    }
+
+    #[test]
+    fn camera_builder_overrides_defaults() {
+        let camera = Camera::builder()
+            .image_width(40)
+            .sample_per_pixel(4)
+            .build();
+        let expected = Camera::init(16.0 / 9.0, 40, 4, 50, 90.0);
+        assert_eq!(camera.image_width, 40);
+        assert_eq!(camera.image_height, expected.image_height);
+        assert_eq!(camera.sample_per_pixel, 4);
+        assert_eq!(camera.max_ray_bounces, 50);
+        assert_eq!(camera.center, expected.center);
+        assert_eq!(camera.pixel_00_loc, expected.pixel_00_loc);
+        assert_eq!(camera.pixel_delta_u, expected.pixel_delta_u);
+        assert_eq!(camera.pixel_delta_v, expected.pixel_delta_v);
+
+        // Focus defaults to the distance to lookat
+        let lookfrom = Point::new(-2., 0., 0.);
+        let camera = Camera::builder()
+            .lookfrom(lookfrom)
+            .defocus_angle(10.)
+            .build();
+        let focused = Camera::builder()
+            .lookfrom(lookfrom)
+            .defocus_angle(10.)
+            .focus_dist(3.)
+            .build();
+        assert_eq!(camera.pixel_00_loc, focused.pixel_00_loc);
+        assert_eq!(camera.defocus_disk_u, focused.defocus_disk_u);
+    }
+
+    #[test]
+    fn render_cancellable_stops_early() {
+        let world = scenes::three_close_spheres().0;
+        let camera = Camera::init(2.0, 8, 2, 5, 90.0).with_seed(2);
+        let should_stop = AtomicBool::new(false);
+        assert_eq!(
+            camera.render_cancellable(&world, false, &should_stop),
+            Some(camera.render(&world, false))
+        );
+
+        // Set before the first row, no row gets rendered
+        should_stop.store(true, Ordering::Relaxed);
+        assert_eq!(camera.render_cancellable(&world, false, &should_stop), None);
+    }
+
+    #[test]
+    fn orthographic_depth_of_a_wall() {
+        let mut world = World::new();
+        world.add(Hittable::Quad(Quad {
+            q: Point::new(3., -5., -5.),
+            u: Vec3::new(0., 10., 0.),
+            v: Vec3::new(0., 0., 10.),
+            material: lambertian(Color::white()),
+        }));
+        let camera = Camera::init(1.0, 8, 1, 1, 90.0)
+            .with_projection(Projection::Orthographic { width: 4. });
+        // Every ray goes straight to the wall facing the camera, 3 units away
+        let depth = camera.render_depth(&world, 6.);
+        let expected = Color::from([0.5; 3]).into();
+        assert!(depth.pixels().all(|pixel| *pixel == expected));
+    }
+
+    #[test]
+    fn orthographic_projection_keeps_sizes() {
+        let material = lambertian(Color::white());
+        let sphere = |x: f64, z: f64| {
+            Hittable::Sphere(Sphere {
+                center: Point::new(x, 0., z),
+                radius: 0.5,
+                material: Arc::clone(&material),
+            })
+        };
+        // Same size, the right one much farther
+        let mut world = World::new();
+        world.add(sphere(3., -1.)).add(sphere(8., 1.));
+        // Pixels covered by the spheres in the left and right halves of the image
+        let coverage = |camera: &Camera| {
+            let normals = camera.render_normals(&world);
+            let covered = |columns: std::ops::Range<u32>| {
+                columns
+                    .flat_map(|x| (0..normals.height()).map(move |y| (x, y)))
+                    .filter(|&(x, y)| normals.get_pixel(x, y).0 != [0, 0, 0])
+                    .count()
+            };
+            let half = normals.width() / 2;
+            (covered(0..half), covered(half..normals.width()))
+        };
+
+        let camera = Camera::init(2.0, 40, 1, 1, 90.0);
+        let (near, far) = coverage(&camera);
+        assert!(near > 2 * far);
+
+        let orthographic = camera.with_projection(Projection::Orthographic { width: 4. });
+        let (near, far) = coverage(&orthographic);
+        assert_eq!(near, far);
+        // Disk of diameter 1 over 10 pixels
+        assert!((near as f64 - 25. * std::f64::consts::PI).abs() < 10.);
+        let ray = orthographic.ray_through(0.25, 0.5);
+        assert_eq!(ray.direction, Vec3::new(1., 0., 0.));
+        assert!((ray.origin.z + 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn adaptive_sampling_stops_early_on_flat_pixels() {
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(3., 0., 0.),
+            radius: 1.,
+            material: lambertian(Color::black()),
+        }));
+        let camera = Camera::init(1.0, 20, 256, 2, 90.0)
+            .with_background(Background::Solid(Color::white()))
+            .with_seed(9)
+            .with_adaptive_sampling(AdaptiveSampling {
+                min_samples: 16,
+                max_variance: 1e-4,
+            });
+        let samples = |x: u32, y: u32| {
+            camera
+                .sampled_radiance(&world, x, y, |sample| {
+                    camera.get_ray(y as usize, x as usize, sample)
+                })
+                .1
+        };
+        // Only the background
+        assert_eq!(samples(0, 0), 16);
+        // Inside the sphere
+        assert_eq!(samples(10, 10), 16);
+        // Some pixels of the middle row are on the edge of the sphere
+        let edge_samples = (0..20).map(|x| samples(x, 10)).max().unwrap();
+        assert!(edge_samples > 16);
+        assert!(edge_samples <= 256);
+    }
+
+    #[test]
+    fn equirectangular_background() {
+        // Each texel gets its own color
+        let image = RgbImage::from_fn(4, 2, |x, y| Rgb([x as u8 * 50, y as u8 * 100, 7]));
+        let background = Background::Equirectangular(ImageTexture::from(image.clone()));
+        let texel = |x: u32, y: u32| -> Color {
+            let [r, g, b] = image.get_pixel(x, y).0;
+            Color { r, g, b }
+        };
+        let toward = |x: f64, y: f64, z: f64| Ray {
+            origin: Point::new(0., 0., 0.),
+            direction: Vec3::new(x, y, z),
+            time: 0.,
+        };
+        // Forward is the middle of the panorama, the horizon its lower half
+        assert_eq!(background.color(&toward(1., -0.1, 0.)), texel(2, 1));
+        assert_eq!(background.color(&toward(1., 0.1, 0.)), texel(2, 0));
+        // The right is a quarter of the panorama away, the back at its edges
+        assert_eq!(background.color(&toward(0.1, -0.1, 1.)), texel(1, 1));
+        assert_eq!(background.color(&toward(-1., -0.1, 0.1)), texel(0, 1));
+        assert_eq!(background.color(&toward(-1., -0.1, -0.1)), texel(3, 1));
+        // Only the direction matters
+        assert_eq!(
+            background.color(&toward(5., -0.5, 0.)),
+            background.color(&toward(1., -0.1, 0.))
+        );
+    }
+
+    #[test]
+    fn hdr_background_reflected_by_metal() {
+        // Uniformly 4 times brighter than white
+        let panorama = ImageTexture {
+            image: Rgb32FImage::from_pixel(4, 2, Rgb([4., 4., 4.])),
+        };
+        let camera =
+            Camera::init(1.0, 1, 1, 5, 90.0).with_background(Background::Equirectangular(panorama));
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(2., 0., 0.),
+            radius: 1.,
+            material: Arc::new(Material {
+                material_type: MaterialType::Metal(Metal { fuzz: 0. }),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        }));
+        let color = camera.ray_color(&camera.ray_through(0.5, 0.5), &world, 5);
+        assert!(color.r > 3.9 && color.g > 3.9 && color.b > 3.9);
+    }
+
+    #[test]
+    fn color3_sanitized() {
+        let color = Color3 {
+            r: f64::NAN,
+            g: f64::INFINITY,
+            b: 3.,
+        };
+        assert_eq!(
+            color.sanitized(f64::INFINITY),
+            Color3 {
+                r: 0.,
+                g: 0.,
+                b: 3.
+            }
+        );
+        assert_eq!(color.sanitized(2.).b, 2.);
+        assert_eq!(Color3::black().sanitized(1.), Color3::black());
+    }
+
+    #[test]
+    fn nan_material_gives_finite_pixels() {
+        // Glass of NaN refraction index scatters rays of NaN directions
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(3., 0., 0.),
+            radius: 1.,
+            material: Arc::new(Material {
+                material_type: MaterialType::Dielectric(Dielectric {
+                    refraction_index: f64::NAN,
+                    absorption: Color3 {
+                        r: f64::NAN,
+                        g: 0.,
+                        b: 0.,
+                    },
+                }),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        }));
+        let camera = Camera::init(1.0, 4, 4, 5, 90.0).with_seed(1);
+        for y in 0..4 {
+            for x in 0..4 {
+                let radiance = camera.pixel_radiance(&world, x, y);
+                assert!(radiance.r.is_finite() && radiance.g.is_finite() && radiance.b.is_finite());
+            }
+        }
+
+        // Clamping fireflies keeps every sample under the limit, black stays black
+        let bright = Camera::init(1.0, 2, 4, 5, 90.0)
+            .with_background(Background::Solid(Color::white()))
+            .with_firefly_clamp(0.5);
+        let empty = World::new();
+        assert_eq!(bright.pixel_radiance(&empty, 0, 0).g, 0.5);
+        let dark = bright.with_background(Background::Solid(Color::black()));
+        assert_eq!(dark.pixel_radiance(&empty, 0, 0), Color3::black());
+    }
+
+    #[test]
+    fn next_event_estimation_keeps_other_emitters() {
+        let mut world = World::new();
+        world.add(Hittable::Quad(Quad {
+            q: Point::new(-5., 0., -5.),
+            u: Vec3::new(10., 0., 0.),
+            v: Vec3::new(0., 0., 10.),
+            material: lambertian(Color::from([0.8, 0.8, 0.8])),
+        }));
+        let light = Arc::new(Material {
+            material_type: MaterialType::DiffuseLight(DiffuseLight {
+                emission: Color3::white(),
+            }),
+            albedo: Texture::Solid(Color::black()),
+        });
+        // Only the quad is sampled directly, the sphere is only found by the scattered rays
+        world.add_light(AreaLight {
+            quad: Quad {
+                q: Point::new(-0.25, 2., -0.25),
+                u: Vec3::new(0.5, 0., 0.),
+                v: Vec3::new(0., 0., 0.5),
+                material: Arc::clone(&light),
+            },
+            shadow_softness: 1.,
+        });
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(1.5, 1.5, 0.),
+            radius: 0.5,
+            material: light,
+        }));
+        let ray = Ray {
+            origin: Point::new(0., 1., 0.),
+            direction: Vec3::new(0., -1., 0.),
+            time: 0.,
+        };
+        let mean = |camera: &Camera| {
+            utils::seed_random(5);
+            let samples = 40_000;
+            (0..samples)
+                .map(|_| camera.ray_color(&ray, &world, 5).g)
+                .sum::<f64>()
+                / samples as f64
+        };
+        let camera =
+            Camera::init(1.0, 1, 1, 5, 90.0).with_background(Background::Solid(Color::black()));
+        let path_traced = mean(&camera);
+        let sampled = mean(&camera.clone().with_next_event_estimation(true));
+        assert!((path_traced - sampled).abs() < 0.1 * path_traced);
+    }
+
+    #[test]
+    fn area_light_shadow_softness() {
+        let mut world = World::new();
+        world.add(Hittable::Quad(Quad {
+            q: Point::new(-5., 0., -5.),
+            u: Vec3::new(10., 0., 0.),
+            v: Vec3::new(0., 0., 10.),
+            material: lambertian(Color::from([0.8, 0.8, 0.8])),
+        }));
+        world.add_light(AreaLight {
+            quad: Quad {
+                q: Point::new(-0.25, 2., -0.25),
+                u: Vec3::new(0.5, 0., 0.),
+                v: Vec3::new(0., 0., 0.5),
+                material: Arc::new(Material {
+                    material_type: MaterialType::DiffuseLight(DiffuseLight {
+                        emission: Color3::white(),
+                    }),
+                    albedo: Texture::Solid(Color::black()),
+                }),
+            },
+            shadow_softness: 0.,
+        });
+        let ray = Ray {
+            origin: Point::new(0., 1., 0.),
+            direction: Vec3::new(0., -1., 0.),
+            time: 0.,
+        };
+        let hit = world.hit(&ray, Interval { min: 0., max: 10. }).unwrap();
+        let mean = |world: &World| {
+            utils::seed_random(8);
+            let samples = 10_000;
+            (0..samples)
+                .map(|_| Camera::direct_light(world, &hit, 0.).g)
+                .sum::<f64>()
+                / samples as f64
+        };
+        let hard = mean(&world);
+        world.lights[0].shadow_softness = 1.;
+        let soft = mean(&world);
+        // Aiming at the center instead of the whole light barely changes the light received
+        assert!((hard - soft).abs() < 0.05 * soft);
+
+        // A small blocker between the floor and the center of the light only hides it from the
+        // shadow rays aimed at its center
+        world.add(Hittable::Sphere(Sphere {
+            center: Point::new(0., 1.5, 0.),
+            radius: 0.05,
+            material: Arc::clone(&hit.material),
+        }));
+        assert!(mean(&world) > 0.5 * soft);
+        world.lights[0].shadow_softness = 0.;
+        assert_eq!(mean(&world), 0.);
+    }
+
+    #[test]
+    fn next_event_estimation_reduces_noise() {
+        let mut world = World::new();
+        world.add(Hittable::Quad(Quad {
+            q: Point::new(-5., 0., -5.),
+            u: Vec3::new(10., 0., 0.),
+            v: Vec3::new(0., 0., 10.),
+            material: lambertian(Color::from([0.8, 0.8, 0.8])),
+        }));
+        // Small light high above the floor
+        world.add_light(AreaLight {
+            quad: Quad {
+                q: Point::new(-0.25, 2., -0.25),
+                u: Vec3::new(0.5, 0., 0.),
+                v: Vec3::new(0., 0., 0.5),
+                material: Arc::new(Material {
+                    material_type: MaterialType::DiffuseLight(DiffuseLight {
+                        emission: Color3::white(),
+                    }),
+                    albedo: Texture::Solid(Color::black()),
+                }),
+            },
+            shadow_softness: 1.,
+        });
+        let ray = Ray {
+            origin: Point::new(0., 1., 0.),
+            direction: Vec3::new(0., -1., 0.),
+            time: 0.,
+        };
+        // Mean and variance of the light seen by the ray
+        let estimate = |camera: &Camera| {
+            utils::seed_random(3);
+            let samples = 20_000;
+            let values: Vec<f64> = (0..samples)
+                .map(|_| camera.ray_color(&ray, &world, 5).g)
+                .collect();
+            let mean = values.iter().sum::<f64>() / samples as f64;
+            let variance =
+                values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / samples as f64;
+            (mean, variance)
+        };
+        let camera =
+            Camera::init(1.0, 1, 1, 5, 90.0).with_background(Background::Solid(Color::black()));
+        let (path_traced_mean, path_traced_variance) = estimate(&camera);
+        let (sampled_mean, sampled_variance) =
+            estimate(&camera.clone().with_next_event_estimation(true));
+        // Same light on average, about the albedo times the light's solid angle over pi
+        let expected = 0.8 * 0.25 / (std::f64::consts::PI * 4.);
+        assert!((sampled_mean - expected).abs() < 0.05 * expected);
+        assert!((path_traced_mean - sampled_mean).abs() < 0.1 * sampled_mean);
+        assert!(sampled_variance * 100. < path_traced_variance);
+    }
 }
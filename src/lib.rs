@@ -0,0 +1,6 @@
+pub mod image;
+pub mod object;
+pub mod scene;
+pub mod scenes;
+pub mod texture;
+pub mod utils;
@@ -1,24 +1,26 @@
+use std::env;
 use std::path::Path;
 
-mod image;
-mod utils;
-use image::Camera;
-
-mod object;
-use object::World;
+use ray_tracing_one_weekend::scene::Scene;
+use ray_tracing_one_weekend::scenes;
 
 fn main() {
-    let objects = World::three_close_spheres();
-    let world = World { objects };
-
-    // camera
-    let aspect_ratio = 3.0 / 2.0;
-    let image_width = 500;
-    let sample_per_pixel = 100;
-    let max_ray_bounces = 50;
     let gamma_corrected = false;
-    let camera = Camera::init(aspect_ratio, image_width, sample_per_pixel, max_ray_bounces);
-    let image = camera.render(&world, gamma_corrected);
+    // The scene file given as argument, or the built-in one
+    let (world, camera) = match env::args().nth(1) {
+        Some(path) => {
+            let scene = Scene::from_file(path).expect("Couldn't load the scene.");
+            (scene.world().build_bvh(), scene.camera.camera())
+        }
+        None => {
+            let (world, camera) = scenes::three_close_spheres();
+            (world.build_bvh(), camera)
+        }
+    };
+    let image = camera.render_with_progress(&world, gamma_corrected, |done, total| {
+        eprint!("\rRendering: {}%", 100 * done / total);
+    });
+    eprintln!();
 
     // Create output file
     let path = Path::new("img.png");
@@ -1,9 +1,14 @@
-use crate::image::{Color, MAX_COLOR_CHANNEL_VALUE};
-use std::{ops, rc::Rc};
+use crate::image::{Color, Color3, MAX_COLOR_CHANNEL_VALUE};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::{fmt, ops, sync::Arc};
 
-use crate::utils::Interval;
+use crate::texture::Texture;
+use crate::utils::{self, Interval};
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// Stored in scene files as the array of its coordinates
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "[f64; 3]", into = "[f64; 3]")]
 pub struct Vec3 {
     pub x: f64,
     pub y: f64,
@@ -11,11 +16,23 @@ pub struct Vec3 {
 }
 
 impl Vec3 {
-    fn len(&self) -> f64 {
-        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    pub const ZERO: Vec3 = Vec3::new(0., 0., 0.);
+    pub const ONE: Vec3 = Vec3::new(1., 1., 1.);
+
+    pub const fn new(x: f64, y: f64, z: f64) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    pub fn len(&self) -> f64 {
+        self.length_squared().sqrt()
     }
 
-    fn normalized(&self) -> Vec3 {
+    /// Square of the length, cheaper than `len` when comparing lengths
+    pub fn length_squared(&self) -> f64 {
+        self.x * self.x + self.y * self.y + self.z * self.z
+    }
+
+    pub fn normalized(&self) -> Vec3 {
         Vec3 {
             x: self.x,
             y: self.y,
@@ -27,18 +44,132 @@ impl Vec3 {
         self.x * v.x + self.y * v.y + self.z * v.z
     }
 
+    /// Mirror reflection of the vector off a surface of unit normal `n`
+    pub fn reflect(&self, n: &Vec3) -> Vec3 {
+        *self - 2.0 * self.dot(n) * *n
+    }
+
+    /// Refraction of the unit vector through a surface of unit normal `n` facing it, following
+    /// Snell's law. `etai_over_etat` is the ratio of the refraction index of the medium the
+    /// vector comes from over the one it enters. Total internal reflection isn't handled.
+    pub fn refract(&self, n: &Vec3, etai_over_etat: f64) -> Vec3 {
+        let cos_theta = f64::min((-*self).dot(n), 1.0);
+        // Split the refracted ray into its components perpendicular and parallel to the normal
+        let perpendicular = etai_over_etat * (*self + cos_theta * *n);
+        let parallel = -(1.0 - perpendicular.length_squared()).abs().sqrt() * *n;
+        perpendicular + parallel
+    }
+
+    /// Right-handed cross product, perpendicular to both vectors
+    pub fn cross(&self, v: &Vec3) -> Vec3 {
+        Vec3 {
+            x: self.y * v.z - self.z * v.y,
+            y: self.z * v.x - self.x * v.z,
+            z: self.x * v.y - self.y * v.x,
+        }
+    }
+
+    /// Random unit vector, uniformly distributed over the unit sphere.
     pub fn random_unit_vector() -> Vec3 {
+        // Rejection sampling: pick points in the [-1,1] cube until one lands inside the unit
+        // sphere. Normalizing points of the cube directly would favor its corners.
+        loop {
+            let p = Vec3 {
+                x: 2.0 * utils::random::<f64>() - 1.0,
+                y: 2.0 * utils::random::<f64>() - 1.0,
+                z: 2.0 * utils::random::<f64>() - 1.0,
+            };
+            let len_squared = p.length_squared();
+            // Points too close to the origin would overflow once normalized
+            if 1e-160 < len_squared && len_squared < 1.0 {
+                return p / len_squared.sqrt();
+            }
+        }
+    }
+
+    /// Random point inside the unit disk of the xy plane
+    pub fn random_in_unit_disk() -> Vec3 {
+        loop {
+            let p = Vec3 {
+                x: 2.0 * utils::random::<f64>() - 1.0,
+                y: 2.0 * utils::random::<f64>() - 1.0,
+                z: 0.,
+            };
+            if p.length_squared() < 1.0 {
+                return p;
+            }
+        }
+    }
+
+    /// Per component absolute value
+    pub fn abs(&self) -> Vec3 {
+        Vec3 {
+            x: self.x.abs(),
+            y: self.y.abs(),
+            z: self.z.abs(),
+        }
+    }
+
+    /// Per component sign: -1 for negative, 1 for positive and 0 for zero components.
+    pub fn signum(&self) -> Vec3 {
+        let sign = |v: f64| if v == 0. { 0. } else { v.signum() };
         Vec3 {
-            x: rand::random::<f64>(),
-            y: rand::random::<f64>(),
-            z: rand::random::<f64>(),
+            x: sign(self.x),
+            y: sign(self.y),
+            z: sign(self.z),
         }
-        .normalized()
+    }
+
+    /// Axis index (0 for x, 1 for y, 2 for z) and value of the largest component.
+    /// On ties, the first axis is returned.
+    pub fn max_component(&self) -> (usize, f64) {
+        (1..3).fold((0, self.x), |(axis, max), n| {
+            if self[n] > max {
+                (n, self[n])
+            } else {
+                (axis, max)
+            }
+        })
+    }
+
+    /// Axis index (0 for x, 1 for y, 2 for z) and value of the smallest component.
+    /// On ties, the first axis is returned.
+    pub fn min_component(&self) -> (usize, f64) {
+        (1..3).fold((0, self.x), |(axis, min), n| {
+            if self[n] < min {
+                (n, self[n])
+            } else {
+                (axis, min)
+            }
+        })
+    }
+
+    /// Two unit tangents completing the unit normal into a right-handed orthonormal frame
+    /// (tangent, bitangent, normal). The orientation of the tangents around the normal is
+    /// arbitrary but always the same for a given normal.
+    pub fn tangent_frame(&self) -> (Vec3, Vec3) {
+        // Any axis not parallel to the normal gives a tangent
+        let helper = if self.x.abs() > 0.9 {
+            Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            }
+        } else {
+            Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            }
+        };
+        let tangent = helper.cross(self).normalized();
+        let bitangent = self.cross(&tangent);
+        (tangent, bitangent)
     }
 
     fn near_zero(&self) -> bool {
         let limit = 1e-8;
-        self.x < limit && self.y < limit && self.z < limit
+        self.x.abs() < limit && self.y.abs() < limit && self.z.abs() < limit
     }
 }
 
@@ -86,6 +217,41 @@ impl ops::Sub<Vec3> for Vec3 {
     }
 }
 
+impl ops::AddAssign<Vec3> for Vec3 {
+    fn add_assign(&mut self, rhs: Vec3) {
+        self.x += rhs.x;
+        self.y += rhs.y;
+        self.z += rhs.z;
+    }
+}
+
+impl ops::SubAssign<Vec3> for Vec3 {
+    fn sub_assign(&mut self, rhs: Vec3) {
+        self.x -= rhs.x;
+        self.y -= rhs.y;
+        self.z -= rhs.z;
+    }
+}
+
+impl ops::MulAssign<f64> for Vec3 {
+    fn mul_assign(&mut self, rhs: f64) {
+        self.x *= rhs;
+        self.y *= rhs;
+        self.z *= rhs;
+    }
+}
+
+impl ops::Neg for Vec3 {
+    type Output = Vec3;
+    fn neg(self) -> Self::Output {
+        Vec3 {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
 impl ops::Mul<f64> for Vec3 {
     type Output = Vec3;
     fn mul(self, rhs: f64) -> Self::Output {
@@ -108,11 +274,38 @@ impl ops::Div<f64> for Vec3 {
     }
 }
 
+impl ops::Index<usize> for Vec3 {
+    type Output = f64;
+    /// Component along the axis `index`: 0 for x, 1 for y and 2 for z
+    fn index(&self, index: usize) -> &Self::Output {
+        match index {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 axis index out of range: {index}"),
+        }
+    }
+}
+
+impl From<[f64; 3]> for Vec3 {
+    fn from([x, y, z]: [f64; 3]) -> Self {
+        Vec3 { x, y, z }
+    }
+}
+
+impl From<Vec3> for [f64; 3] {
+    fn from(v: Vec3) -> Self {
+        [v.x, v.y, v.z]
+    }
+}
+
 pub type Point = Vec3;
 
 pub struct Ray {
     pub origin: Point,
     pub direction: Vec3,
+    /// Moment the ray is cast at, in [0,1) over the shutter interval
+    pub time: f64,
 }
 
 impl Ray {
@@ -122,20 +315,26 @@ impl Ray {
 
     /// Background, blue gradient based on y coordinates.
     pub fn blue_lerp(ray: &Ray) -> Color {
-        let normalized = ray.direction.normalized();
-        // a = 1 when y = 1.0, a = 0 when y = -1.0
-        let a = 0.5 * (normalized.y + 1.0);
-        let start_color = Color {
+        let white = Color {
             r: MAX_COLOR_CHANNEL_VALUE,
             g: MAX_COLOR_CHANNEL_VALUE,
             b: MAX_COLOR_CHANNEL_VALUE,
         };
-        let end_color = Color {
+        let blue = Color {
             r: (MAX_COLOR_CHANNEL_VALUE as f64 * 0.5) as u8,
             g: (MAX_COLOR_CHANNEL_VALUE as f64 * 0.7) as u8,
             b: (MAX_COLOR_CHANNEL_VALUE as f64 * 1.0) as u8,
         };
-        (1.0 - a) * start_color + a * end_color
+        Ray::gradient_lerp(ray, white, blue)
+    }
+
+    /// Vertical gradient seen by the ray, `bottom` when looking straight down and `top` when
+    /// looking straight up
+    pub fn gradient_lerp(ray: &Ray, bottom: Color, top: Color) -> Color {
+        let normalized = ray.direction.normalized();
+        // a = 1 when y = 1.0, a = 0 when y = -1.0
+        let a = 0.5 * (normalized.y + 1.0);
+        Color::lerp(bottom, top, a)
     }
 }
 
@@ -145,7 +344,13 @@ pub struct HitRecord {
     pub normal: Vec3,
     t: f64,
     front_face: bool,
-    material: Rc<Material>,
+    pub material: Arc<Material>,
+    /// Surface coordinates of the hit point, used to sample textures
+    pub u: f64,
+    pub v: f64,
+    /// Name of the object that was hit, if it was given one. Shared with the object, so that
+    /// hits don't copy it.
+    pub name: Option<Arc<str>>,
 }
 
 impl HitRecord {
@@ -156,18 +361,407 @@ impl HitRecord {
         ray.direction.dot(outward_normal) < 0.
     }
 }
+/// Objects are stored in scene files tagged with their `type`. Acceleration structures and
+/// objects built from derived data (meshes, boxes, rotations) can't be stored.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum Hittable {
     Sphere(Sphere),
+    MovingSphere(MovingSphere),
+    Named(Named),
+    #[serde(skip)]
+    Bvh(BvhNode),
+    Plane(Plane),
+    Quad(Quad),
+    Triangle(Triangle),
+    #[serde(skip)]
+    Mesh(Mesh),
+    #[serde(skip)]
+    Box(BoxPrim),
+    Translate(Translate),
+    #[serde(skip)]
+    RotateY(RotateY),
+    Scale(Scale),
+    ConstantMedium(ConstantMedium),
+    Cylinder(Cylinder),
 }
 
 impl Hittable {
     fn hit(&self, ray: &Ray, interval: Interval) -> Option<HitRecord> {
         match self {
-            Hittable::Sphere(sphere) => Hittable::hit_sphere(sphere, ray, interval),
+            Hittable::Sphere(sphere) => Hittable::hit_sphere(
+                sphere.center,
+                sphere.radius,
+                &sphere.material,
+                ray,
+                interval,
+            ),
+            Hittable::MovingSphere(sphere) => Hittable::hit_sphere(
+                sphere.center_at(ray.time),
+                sphere.radius,
+                &sphere.material,
+                ray,
+                interval,
+            ),
+            Hittable::Named(named) => Hittable::hit_named(named, ray, interval),
+            Hittable::Bvh(node) => Hittable::hit_bvh(node, ray, interval),
+            Hittable::Plane(plane) => Hittable::hit_plane(plane, ray, interval),
+            Hittable::Quad(quad) => Hittable::hit_quad(quad, ray, interval),
+            Hittable::Triangle(triangle) => Hittable::hit_triangle(triangle, ray, interval),
+            Hittable::Mesh(mesh) => mesh
+                .bvh
+                .as_ref()
+                .and_then(|bvh| Hittable::hit_bvh(bvh, ray, interval)),
+            Hittable::Box(prim) => prim.sides.hit(ray, interval),
+            Hittable::Translate(translate) => Hittable::hit_translate(translate, ray, interval),
+            Hittable::RotateY(rotate) => Hittable::hit_rotate_y(rotate, ray, interval),
+            Hittable::Scale(scale) => Hittable::hit_scale(scale, ray, interval),
+            Hittable::ConstantMedium(medium) => {
+                Hittable::hit_constant_medium(medium, ray, interval)
+            }
+            Hittable::Cylinder(cylinder) => Hittable::hit_cylinder(cylinder, ray, interval),
+        }
+    }
+
+    /// Smallest axis aligned box enclosing the object
+    pub fn bounding_box(&self) -> Aabb {
+        match self {
+            Hittable::Sphere(sphere) => sphere.bounding_box(),
+            Hittable::MovingSphere(sphere) => sphere.bounding_box(),
+            Hittable::Named(named) => named.object.bounding_box(),
+            Hittable::Bvh(node) => node.bbox,
+            Hittable::Plane(plane) => plane.bounding_box(),
+            Hittable::Quad(quad) => quad.bounding_box(),
+            Hittable::Triangle(triangle) => triangle.bounding_box(),
+            Hittable::Mesh(mesh) => mesh.bounding_box(),
+            Hittable::Box(prim) => prim.bbox,
+            Hittable::Translate(translate) => {
+                translate.object.bounding_box().offset(translate.offset)
+            }
+            Hittable::RotateY(rotate) => rotate.bbox,
+            Hittable::Scale(scale) => scale.object.bounding_box().scale(scale.factor),
+            Hittable::ConstantMedium(medium) => medium.boundary.bounding_box(),
+            Hittable::Cylinder(cylinder) => cylinder.bounding_box(),
+        }
+    }
+
+    fn hit_triangle(triangle: &Triangle, ray: &Ray, interval: Interval) -> Option<HitRecord> {
+        // Möller–Trumbore: solve origin + t * direction = a + u * (b - a) + v * (c - a)
+        // with Cramer's rule, (u, v) being the barycentric coordinates of the hit point.
+        let edge_1 = triangle.b - triangle.a;
+        let edge_2 = triangle.c - triangle.a;
+        let p_vec = ray.direction.cross(&edge_2);
+        let determinant = edge_1.dot(&p_vec);
+        // The ray is parallel to the triangle, or hits a culled back face
+        if determinant.abs() < 1e-8 || (triangle.cull_backfaces && determinant < 1e-8) {
+            return None;
+        }
+        let inverse_determinant = 1.0 / determinant;
+
+        let t_vec = ray.origin - triangle.a;
+        let u = t_vec.dot(&p_vec) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q_vec = t_vec.cross(&edge_1);
+        let v = ray.direction.dot(&q_vec) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge_2.dot(&q_vec) * inverse_determinant;
+        if !interval.surrounds(t) {
+            return None;
+        }
+
+        let p = ray.at(t);
+        // The side hit is given by the geometry, whatever the shading normal
+        let outward_normal = edge_1.cross(&edge_2).normalized();
+        let front_face = HitRecord::is_hit_from_front(ray, &outward_normal);
+        let shading_normal = match triangle.normals {
+            Some([normal_a, normal_b, normal_c]) => {
+                ((1. - u - v) * normal_a + u * normal_b + v * normal_c).normalized()
+            }
+            None => outward_normal,
+        };
+        // Make normal point against the ray
+        let normal = if front_face {
+            shading_normal
+        } else {
+            -shading_normal
+        };
+        Some(HitRecord {
+            t,
+            p,
+            normal,
+            front_face,
+            material: Arc::clone(&triangle.material),
+            u,
+            v,
+            name: None,
+        })
+    }
+
+    fn hit_quad(quad: &Quad, ray: &Ray, interval: Interval) -> Option<HitRecord> {
+        // Intersect the plane containing the quad first
+        let n = quad.u.cross(&quad.v);
+        let denominator = ray.direction.dot(&n);
+        // The ray is parallel to the plane
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+        let t = (quad.q - ray.origin).dot(&n) / denominator;
+        if !interval.surrounds(t) {
+            return None;
+        }
+        // Then check that the hit point lies within the edges
+        let p = ray.at(t);
+        let (alpha, beta) = quad.planar_coordinates(p);
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        let outward_normal = n.normalized();
+        let front_face = HitRecord::is_hit_from_front(ray, &outward_normal);
+        // Make normal point against the ray
+        let normal = if front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+        Some(HitRecord {
+            t,
+            p,
+            normal,
+            front_face,
+            material: Arc::clone(&quad.material),
+            u: alpha,
+            v: beta,
+            name: None,
+        })
+    }
+
+    fn hit_plane(plane: &Plane, ray: &Ray, interval: Interval) -> Option<HitRecord> {
+        // Points P of the plane verify (P - point).normal = 0, with P = origin + t * direction
+        // => t = (point - origin).normal / direction.normal
+        let denominator = ray.direction.dot(&plane.normal);
+        // The ray is parallel to the plane
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+        let t = (plane.point - ray.origin).dot(&plane.normal) / denominator;
+        if !interval.surrounds(t) {
+            return None;
+        }
+        let p = ray.at(t);
+        let outward_normal = plane.normal.normalized();
+        let front_face = HitRecord::is_hit_from_front(ray, &outward_normal);
+        // Make normal point against the ray
+        let normal = if front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+        Some(HitRecord {
+            t,
+            p,
+            normal,
+            front_face,
+            material: Arc::clone(&plane.material),
+            u: 0.,
+            v: 0.,
+            name: None,
+        })
+    }
+
+    fn hit_bvh(node: &BvhNode, ray: &Ray, mut interval: Interval) -> Option<HitRecord> {
+        // Rays missing the box can't hit any of the objects inside
+        if !node.bbox.hit(ray, interval) {
+            return None;
+        }
+        let left_hit = node.left.hit(ray, interval);
+        // The right child only matters if it is hit before the left one
+        if let Some(hit) = &left_hit {
+            interval.max = hit.t;
+        }
+        node.right.hit(ray, interval).or(left_hit)
+    }
+
+    fn hit_named(named: &Named, ray: &Ray, interval: Interval) -> Option<HitRecord> {
+        let mut hit = named.object.hit(ray, interval)?;
+        // Nested names: the innermost object is the one that was actually hit
+        hit.name.get_or_insert_with(|| Arc::clone(&named.name));
+        Some(hit)
+    }
+
+    fn hit_translate(translate: &Translate, ray: &Ray, interval: Interval) -> Option<HitRecord> {
+        // Moving the object by the offset is the same as moving the ray by the opposite
+        let moved_ray = Ray {
+            origin: ray.origin - translate.offset,
+            direction: ray.direction,
+            time: ray.time,
+        };
+        let mut hit = translate.object.hit(&moved_ray, interval)?;
+        hit.p += translate.offset;
+        Some(hit)
+    }
+
+    fn hit_rotate_y(rotate: &RotateY, ray: &Ray, interval: Interval) -> Option<HitRecord> {
+        // Rotate the ray into the object space by the opposite angle
+        let object_ray = Ray {
+            origin: rotate.to_object(ray.origin),
+            direction: rotate.to_object(ray.direction),
+            time: ray.time,
+        };
+        let mut hit = rotate.object.hit(&object_ray, interval)?;
+        // Rotations preserve angles, the hit stays on the same side of the surface
+        hit.p = rotate.to_world(hit.p);
+        hit.normal = rotate.to_world(hit.normal);
+        Some(hit)
+    }
+
+    fn hit_scale(scale: &Scale, ray: &Ray, interval: Interval) -> Option<HitRecord> {
+        // The scaling is linear, the hit is at the same t in the object space
+        let object_ray = Ray {
+            origin: scale.to_object(ray.origin),
+            direction: scale.to_object(ray.direction),
+            time: ray.time,
+        };
+        let mut hit = scale.object.hit(&object_ray, interval)?;
+        hit.p = scale.to_world(hit.p);
+        // Normals are transformed by the inverse transpose of the scaling, so that they stay
+        // perpendicular to the stretched surface. It keeps them on the same side of the ray.
+        hit.normal = scale.to_object(hit.normal).normalized();
+        Some(hit)
+    }
+
+    fn hit_cylinder(cylinder: &Cylinder, ray: &Ray, interval: Interval) -> Option<HitRecord> {
+        let axis = cylinder.axis.normalized();
+        let oc = ray.origin - cylinder.base;
+        // Components of the ray perpendicular to the axis, the side is the circle of radius r
+        // in the plane perpendicular to it
+        let direction_perpendicular = ray.direction - ray.direction.dot(&axis) * axis;
+        let oc_perpendicular = oc - oc.dot(&axis) * axis;
+
+        // Closest of the side and cap hits within the interval: (t, outward normal, u, v)
+        let mut closest: Option<(f64, Vec3, f64, f64)> = None;
+        let mut consider = |t: f64, outward_normal: Vec3, u: f64, v: f64| {
+            if interval.surrounds(t) && closest.is_none_or(|(closest_t, ..)| t < closest_t) {
+                closest = Some((t, outward_normal, u, v));
+            }
+        };
+
+        let a = direction_perpendicular.length_squared();
+        // Rays parallel to the axis can only hit the caps
+        if a > 1e-12 {
+            let h = -direction_perpendicular.dot(&oc_perpendicular);
+            let c = oc_perpendicular.length_squared() - cylinder.radius * cylinder.radius;
+            let discriminant = h * h - a * c;
+            if discriminant >= 0. {
+                for t in [(h - discriminant.sqrt()) / a, (h + discriminant.sqrt()) / a] {
+                    let along_axis = (oc + t * ray.direction).dot(&axis);
+                    if (0.0..=cylinder.height).contains(&along_axis) {
+                        let outward_normal =
+                            (oc_perpendicular + t * direction_perpendicular) / cylinder.radius;
+                        let u = cylinder.angle_fraction(&outward_normal);
+                        consider(t, outward_normal, u, along_axis / cylinder.height);
+                    }
+                }
+            }
+        }
+
+        if cylinder.capped {
+            let direction_along_axis = ray.direction.dot(&axis);
+            if direction_along_axis.abs() > 1e-12 {
+                for (cap_height, outward_normal) in [(0., -axis), (cylinder.height, axis)] {
+                    let t = (cap_height - oc.dot(&axis)) / direction_along_axis;
+                    let from_axis = oc_perpendicular + t * direction_perpendicular;
+                    if from_axis.length_squared() <= cylinder.radius * cylinder.radius {
+                        let u = cylinder.angle_fraction(&from_axis);
+                        consider(t, outward_normal, u, from_axis.len() / cylinder.radius);
+                    }
+                }
+            }
+        }
+
+        let (t, outward_normal, u, v) = closest?;
+        let front_face = HitRecord::is_hit_from_front(ray, &outward_normal);
+        // Make normal point against the ray
+        let normal = if front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+        Some(HitRecord {
+            t,
+            p: ray.at(t),
+            normal,
+            front_face,
+            material: Arc::clone(&cylinder.material),
+            u,
+            v,
+            name: None,
+        })
+    }
+
+    fn hit_constant_medium(
+        medium: &ConstantMedium,
+        ray: &Ray,
+        interval: Interval,
+    ) -> Option<HitRecord> {
+        // Where the ray enters and leaves the boundary, even if it starts inside of it
+        let entry = medium.boundary.hit(
+            ray,
+            Interval {
+                min: f64::NEG_INFINITY,
+                max: f64::INFINITY,
+            },
+        )?;
+        let exit = medium.boundary.hit(
+            ray,
+            Interval {
+                min: entry.t + 0.0001,
+                max: f64::INFINITY,
+            },
+        )?;
+        let t_entry = entry.t.max(interval.min).max(0.);
+        let t_exit = exit.t.min(interval.max);
+        if t_entry >= t_exit {
+            return None;
+        }
+
+        // The probability of scattering over a small distance is proportional to the density
+        let ray_length = ray.direction.len();
+        let distance_inside = (t_exit - t_entry) * ray_length;
+        let hit_distance = -(1.0 - utils::random::<f64>()).ln() / medium.density;
+        if hit_distance > distance_inside {
+            return None;
         }
+
+        let t = t_entry + hit_distance / ray_length;
+        Some(HitRecord {
+            t,
+            p: ray.at(t),
+            // The phase function ignores the normal, any will do
+            normal: Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            front_face: true,
+            material: Arc::clone(&medium.phase_material),
+            u: 0.,
+            v: 0.,
+            name: None,
+        })
     }
 
-    fn hit_sphere(sphere: &Sphere, ray: &Ray, interval: Interval) -> Option<HitRecord> {
+    fn hit_sphere(
+        center: Point,
+        radius: f64,
+        material: &Arc<Material>,
+        ray: &Ray,
+        interval: Interval,
+    ) -> Option<HitRecord> {
         // Finds t for quadratic equation x(t)^2 + y(t)^2 + z(t)^2 - r^2 = 0,
         // with:  ray = origin + t * direction
         // => t^2d.d - 2td.(C-Q) + (C-Q).(C-Q) - r^2 = 0
@@ -175,11 +769,11 @@ impl Hittable {
         // C: sphere center
         // r: sphere radius
         // Q: ray origin
-        let qc = sphere.center - ray.origin; // ray origin to sphere center
-        let a = ray.direction.dot(&ray.direction);
+        let qc = center - ray.origin; // ray origin to sphere center
+        let a = ray.direction.length_squared();
         // h = b / -2, simplifies the equation of roots
         let h = ray.direction.dot(&qc);
-        let c = qc.dot(&qc) - sphere.radius * sphere.radius;
+        let c = qc.length_squared() - radius * radius;
         let discriminant = h * h - a * c;
         if discriminant < 0. {
             return None;
@@ -188,251 +782,3253 @@ impl Hittable {
         let discriminant_sqrt = discriminant.sqrt();
 
         let mut root = (h - discriminant_sqrt) / a;
-        if !interval.contains(root) {
+        if !interval.surrounds(root) {
             root = (h + discriminant_sqrt) / a;
-            if !interval.contains(root) {
+            if !interval.surrounds(root) {
                 return None;
             }
         }
         let t = root;
         let p = ray.at(root);
-        let outward_normal = (p - sphere.center) / sphere.radius;
+        // A negative radius turns the sphere inside out: its outward normal points to the center,
+        // so that a glass sphere of negative radius nested in another one makes a hollow bubble.
+        let outward_normal = (p - center) / radius;
+        // The texture doesn't depend on the sign of the radius
+        let (u, v) = Sphere::get_sphere_uv(&((p - center) / radius.abs()));
         let front_face = HitRecord::is_hit_from_front(ray, &outward_normal);
         // Make normal point outward the surface
         let normal = if front_face {
             outward_normal
         } else {
-            -1.0 * outward_normal
+            -outward_normal
         };
         Some(HitRecord {
             t,
             p,
             normal,
             front_face,
-            material: Rc::clone(&sphere.material),
+            material: Arc::clone(material),
+            u,
+            v,
+            name: None,
         })
     }
 }
 
 pub struct ScatteredRay {
     pub ray: Ray,
-    pub attenuation: Color,
+    /// Share of the light carried back along the ray that reaches the hit, per channel
+    pub attenuation: Color3,
 }
 
 impl ScatteredRay {
-    pub fn scatter(hit: &HitRecord, incident_ray: &Ray) -> ScatteredRay {
-        let mut scatter_direction: Vec3;
-        match hit.material.material_type {
-            MaterialType::Lambertian => {
-                // Diffuse objects reflect light in random directions
-                // Adding normal so that scatters are in general closer to the normal
-                scatter_direction = Vec3::random_unit_vector() + hit.normal;
-                // If the random unit vector is opposite to the normal, the scatter is the null
-                // vector. To prevent troubles with this (NaN, Infinity ...) we use the normal
-                // as the scatter direction in case the vector is null.
-                if scatter_direction.near_zero() {
-                    scatter_direction = hit.normal;
-                }
-            }
-            MaterialType::Metal { fuzz } => {
-                scatter_direction = (incident_ray.direction
-                    - 2.0 * incident_ray.direction.dot(&hit.normal) * hit.normal)
-                    .normalized()
-                    + fuzz * Vec3::random_unit_vector();
-            }
-        }
+    /// Rays scattered by the material that was hit, none if the material doesn't scatter light.
+    /// Their contributions are averaged, current materials scatter a single ray.
+    pub fn scatter(hit: &HitRecord, incident_ray: &Ray) -> Vec<ScatteredRay> {
+        hit.material
+            .material_type
+            .scatterer()
+            .scatter(hit, incident_ray)
+    }
+
+    /// Ray leaving the surface in `direction`, attenuated by the albedo of the material.
+    pub fn off_surface(hit: &HitRecord, incident_ray: &Ray, direction: Vec3) -> ScatteredRay {
         // Chck if the scatter is in the same direction as the normal
         // Otherwise, the scatter would be pointing inside the object.
-        scatter_direction = if scatter_direction.dot(&hit.normal) >= 0. {
-            scatter_direction
+        let direction = if direction.dot(&hit.normal) >= 0. {
+            direction
         } else {
-            -1.0 * scatter_direction
-        };
-        let scattered_ray = Ray {
-            origin: hit.p,
-            direction: scatter_direction,
+            -direction
         };
         ScatteredRay {
-            ray: scattered_ray,
-            attenuation: hit.material.albedo,
+            ray: Ray {
+                origin: hit.p,
+                direction,
+                // Scattering is instantaneous
+                time: incident_ray.time,
+            },
+            attenuation: Color3::from(hit.material.albedo.value(hit.u, hit.v, &hit.p)),
+        }
+    }
+
+    /// Direction of a ray going through a dielectric surface, following Snell's law.
+    /// The medium outside of the object is considered to be air, with a refraction index of 1.
+    /// The ray is reflected instead when `reflect_sample`, uniform in [0,1), is below the
+    /// reflectance of the surface.
+    fn dielectric_direction(
+        hit: &HitRecord,
+        incident_ray: &Ray,
+        refraction_index: f64,
+        reflect_sample: f64,
+    ) -> Vec3 {
+        // Ratio of the refraction index of the medium the ray leaves over the one it enters
+        let ratio = if hit.front_face {
+            1.0 / refraction_index
+        } else {
+            refraction_index
+        };
+        let unit_direction = incident_ray.direction.normalized();
+        let cos_theta = f64::min((-unit_direction).dot(&hit.normal), 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+        if ratio * sin_theta > 1.0 || reflectance(cos_theta, ratio) > reflect_sample {
+            // Either Snell's law has no solution and the ray can't refract (total internal
+            // reflection), or the ray is one of those reflected by the surface
+            unit_direction.reflect(&hit.normal)
+        } else {
+            unit_direction.refract(&hit.normal, ratio)
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct Material {
-    pub material_type: MaterialType,
-    pub albedo: Color,
+/// Schlick's approximation of the share of light reflected by a dielectric surface, for a ray
+/// hitting it with an angle of the given cosine.
+pub fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
+    let r0 = ((1.0 - refraction_index) / (1.0 + refraction_index)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub enum MaterialType {
-    Lambertian,
-    Metal { fuzz: f64 },
+/// How a material scatters and emits light. Each material is a type implementing it, and
+/// `MaterialType::Custom` takes materials defined outside of this module.
+pub trait Scatter: fmt::Debug + Send + Sync {
+    /// Rays scattered at the hit, none if the material doesn't scatter light.
+    fn scatter(&self, hit: &HitRecord, incident_ray: &Ray) -> Vec<ScatteredRay>;
+
+    /// Light emitted by the material, black for materials that aren't light sources.
+    fn emitted(&self) -> Color3 {
+        Color3::black()
+    }
+
+    /// Scalar estimate of the fraction of light carried on by a scattered ray, in [0,1], for
+    /// the given albedo. Meant to drive termination probabilities of paths, like russian roulette.
+    fn average_reflectance(&self, albedo: &Texture) -> f64 {
+        let albedo = albedo.mean_color();
+        (albedo.r as f64 + albedo.g as f64 + albedo.b as f64)
+            / (3.0 * MAX_COLOR_CHANNEL_VALUE as f64)
+    }
 }
 
-pub struct Sphere {
-    pub center: Point,
-    pub radius: f64,
-    pub material: Rc<Material>,
+/// Diffuse objects reflect light in random directions
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lambertian;
+
+impl Scatter for Lambertian {
+    fn scatter(&self, hit: &HitRecord, incident_ray: &Ray) -> Vec<ScatteredRay> {
+        // Adding normal so that scatters are in general closer to the normal
+        let mut scatter_direction = Vec3::random_unit_vector() + hit.normal;
+        // If the random unit vector is opposite to the normal, the scatter is the null
+        // vector. To prevent troubles with this (NaN, Infinity ...) we use the normal
+        // as the scatter direction in case the vector is null.
+        if scatter_direction.near_zero() {
+            scatter_direction = hit.normal;
+        }
+        vec![ScatteredRay::off_surface(
+            hit,
+            incident_ray,
+            scatter_direction,
+        )]
+    }
 }
 
-pub struct World {
-    pub objects: Vec<Rc<Hittable>>,
+/// Mirror reflection, blurred by the fuzz
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Metal {
+    pub fuzz: f64,
 }
 
-impl World {
-    pub fn hit(&self, ray: &Ray, mut interval: Interval) -> Option<HitRecord> {
-        let mut closest_hit: Option<HitRecord> = None;
+impl Scatter for Metal {
+    fn scatter(&self, hit: &HitRecord, incident_ray: &Ray) -> Vec<ScatteredRay> {
+        // Beyond 1, the fuzz could send most rays back into the surface
+        let fuzz = self.fuzz.clamp(0.0, 1.0);
+        let scatter_direction = incident_ray.direction.reflect(&hit.normal).normalized()
+            + fuzz * Vec3::random_unit_vector();
+        vec![ScatteredRay::off_surface(
+            hit,
+            incident_ray,
+            scatter_direction,
+        )]
+    }
+}
 
-        for object in &self.objects {
-            if let Some(hit) = object.hit(ray, interval) {
-                interval.max = hit.t;
-                closest_hit = Some(hit);
-            }
-        }
+/// Brushed metal, the reflection is blurred by `fuzz_u` along the tangent of the surface and
+/// by `fuzz_v` along its bitangent, see `Vec3::tangent_frame`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnisotropicMetal {
+    pub fuzz_u: f64,
+    pub fuzz_v: f64,
+}
 
-        closest_hit
+impl Scatter for AnisotropicMetal {
+    fn scatter(&self, hit: &HitRecord, incident_ray: &Ray) -> Vec<ScatteredRay> {
+        let (tangent, bitangent) = hit.normal.tangent_frame();
+        let perturbation = Vec3::random_unit_vector();
+        let scatter_direction = incident_ray.direction.reflect(&hit.normal).normalized()
+            + self.fuzz_u.clamp(0.0, 1.0) * perturbation.dot(&tangent) * tangent
+            + self.fuzz_v.clamp(0.0, 1.0) * perturbation.dot(&bitangent) * bitangent;
+        vec![ScatteredRay::off_surface(
+            hit,
+            incident_ray,
+            scatter_direction,
+        )]
     }
+}
 
-    pub fn three_close_spheres() -> Vec<Rc<Hittable>> {
-        let material_ground = Rc::new(Material {
-            material_type: MaterialType::Lambertian,
-            albedo: Color::from([0.5, 0.5, 0.5]),
-        });
-        let material_center = Rc::new(Material {
-            material_type: MaterialType::Lambertian,
-            albedo: Color::from([0.1, 0.2, 0.5]),
-        });
-        let material_left = Rc::new(Material {
-            material_type: MaterialType::Metal { fuzz: 1.0 },
-            albedo: Color::from([0.8, 0.8, 0.8]),
-        });
-        let material_right = Rc::new(Material {
-            material_type: MaterialType::Metal { fuzz: 0.1 },
-            albedo: Color::from([0.8, 0.6, 0.2]),
-        });
+/// Glass, reflecting or refracting the ray
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Dielectric {
+    pub refraction_index: f64,
+    /// Share of each channel absorbed per unit of distance traveled inside the material,
+    /// black for clear glass
+    #[serde(default, skip_serializing_if = "Color3::is_black")]
+    pub absorption: Color3,
+}
 
-        vec![
-            Rc::new(Hittable::Sphere(Sphere {
-                center: Point {
-                    x: 0.,
-                    y: -100.5,
+impl Dielectric {
+    /// Share of each channel left after traveling `distance` inside the material
+    fn transmittance(&self, distance: f64) -> Color3 {
+        let transmittance = |absorption: f64| (-absorption * distance).exp();
+        Color3 {
+            r: transmittance(self.absorption.r),
+            g: transmittance(self.absorption.g),
+            b: transmittance(self.absorption.b),
+        }
+    }
+}
+
+impl Scatter for Dielectric {
+    fn scatter(&self, hit: &HitRecord, incident_ray: &Ray) -> Vec<ScatteredRay> {
+        // Hitting the back of the surface, the ray traveled inside the glass since it entered
+        // it, and was absorbed along the way (Beer's law)
+        let attenuation = if hit.front_face {
+            Color3::white()
+        } else {
+            self.transmittance(hit.t * incident_ray.direction.len())
+        };
+        // Refracted rays go through the surface, they must not be flipped toward the
+        // normal like the other scatters.
+        vec![ScatteredRay {
+            ray: Ray {
+                origin: hit.p,
+                direction: ScatteredRay::dielectric_direction(
+                    hit,
+                    incident_ray,
+                    self.refraction_index,
+                    utils::random::<f64>(),
+                ),
+                time: incident_ray.time,
+            },
+            attenuation,
+        }]
+    }
+
+    /// Light is either reflected or refracted, only tinted glass absorbs some of it. The
+    /// distance traveled inside isn't known in advance, a unit of distance is assumed.
+    fn average_reflectance(&self, _albedo: &Texture) -> f64 {
+        let transmittance = self.transmittance(1.);
+        (transmittance.r + transmittance.g + transmittance.b) / 3.
+    }
+}
+
+/// Light source, emitting the same color in all directions. The emission is linear light,
+/// it can be brighter than white so that small lights can light a whole scene.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DiffuseLight {
+    pub emission: Color3,
+}
+
+impl Scatter for DiffuseLight {
+    /// Lights only emit, the path stops there
+    fn scatter(&self, _hit: &HitRecord, _incident_ray: &Ray) -> Vec<ScatteredRay> {
+        vec![]
+    }
+
+    fn emitted(&self) -> Color3 {
+        self.emission
+    }
+
+    fn average_reflectance(&self, _albedo: &Texture) -> f64 {
+        0.0
+    }
+}
+
+/// Scatters uniformly in all directions, regardless of the normal. Phase function of
+/// participating media like fog or smoke.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Isotropic;
+
+impl Scatter for Isotropic {
+    fn scatter(&self, hit: &HitRecord, incident_ray: &Ray) -> Vec<ScatteredRay> {
+        // Media have no surface, the scatter must not be flipped toward the normal
+        vec![ScatteredRay {
+            ray: Ray {
+                origin: hit.p,
+                direction: Vec3::random_unit_vector(),
+                time: incident_ray.time,
+            },
+            attenuation: Color3::from(hit.material.albedo.value(hit.u, hit.v, &hit.p)),
+        }]
+    }
+}
+
+/// Material defined outside of this module. Two custom materials are equal when they share
+/// the same instance.
+#[derive(Clone, Debug)]
+pub struct CustomMaterial(pub Arc<dyn Scatter>);
+
+impl PartialEq for CustomMaterial {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Stored in scene files as the object of its type, with an `albedo` texture, white when
+/// omitted
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Material {
+    #[serde(flatten)]
+    pub material_type: MaterialType,
+    #[serde(default = "Material::default_albedo")]
+    pub albedo: Texture,
+}
+
+impl Material {
+    /// Lights and glass don't need an albedo
+    fn default_albedo() -> Texture {
+        Texture::Solid(Color::white())
+    }
+
+    /// Light emitted by the material, black for materials that aren't light sources.
+    pub fn emitted(&self) -> Color3 {
+        self.material_type.scatterer().emitted()
+    }
+
+    /// Scalar estimate of the fraction of light carried on by a scattered ray, in [0,1].
+    /// Meant to drive termination probabilities of paths, like russian roulette.
+    pub fn average_reflectance(&self) -> f64 {
+        self.material_type
+            .scatterer()
+            .average_reflectance(&self.albedo)
+    }
+}
+
+/// Pick a material for the grid cell (x, z) of a procedural scene.
+/// The material only depends on the cell coordinates and the seed, not on the order in which
+/// the cells are generated, so adding an object elsewhere doesn't change the others.
+pub fn material_from_seed(x: i32, z: i32, seed: u64) -> Material {
+    let mut rng = StdRng::seed_from_u64(utils::hash_seed(seed, x as u64, z as u64));
+
+    let choose_material = rng.gen::<f64>();
+    if choose_material < 0.8 {
+        let mut albedo = || rng.gen::<f64>() * rng.gen::<f64>();
+        Material {
+            material_type: MaterialType::Lambertian(Lambertian),
+            albedo: Texture::Solid(Color::from([albedo(), albedo(), albedo()])),
+        }
+    } else if choose_material < 0.95 {
+        let mut albedo = || rng.gen_range(0.5..1.0);
+        let albedo = Color::from([albedo(), albedo(), albedo()]);
+        Material {
+            material_type: MaterialType::Metal(Metal {
+                fuzz: rng.gen_range(0.0..0.5),
+            }),
+            albedo: Texture::Solid(albedo),
+        }
+    } else {
+        Material {
+            material_type: MaterialType::Dielectric(Dielectric {
+                refraction_index: 1.5,
+                absorption: Color3::black(),
+            }),
+            albedo: Texture::Solid(Color::white()),
+        }
+    }
+}
+
+/// Stored in scene files with a `type` field naming the material, next to its parameters
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialType {
+    Lambertian(Lambertian),
+    Metal(Metal),
+    AnisotropicMetal(AnisotropicMetal),
+    Dielectric(Dielectric),
+    DiffuseLight(DiffuseLight),
+    Isotropic(Isotropic),
+    /// Can't be stored in scene files
+    #[serde(skip)]
+    Custom(CustomMaterial),
+}
+
+impl MaterialType {
+    /// The material handling the hits
+    pub fn scatterer(&self) -> &dyn Scatter {
+        match self {
+            MaterialType::Lambertian(material) => material,
+            MaterialType::Metal(material) => material,
+            MaterialType::AnisotropicMetal(material) => material,
+            MaterialType::Dielectric(material) => material,
+            MaterialType::DiffuseLight(material) => material,
+            MaterialType::Isotropic(material) => material,
+            MaterialType::Custom(material) => material.0.as_ref(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Sphere {
+    pub center: Point,
+    pub radius: f64,
+    pub material: Arc<Material>,
+}
+
+impl Sphere {
+    pub fn bounding_box(&self) -> Aabb {
+        let radius = Vec3 {
+            x: self.radius,
+            y: self.radius,
+            z: self.radius,
+        };
+        // The radius may be negative, from_points orders the corners
+        Aabb::from_points(self.center - radius, self.center + radius)
+    }
+
+    /// Maps a point of the unit sphere centered at the origin to `(u, v)` in `[0, 1]`.
+    /// `u` goes around the y axis starting from -x, `v` goes from the bottom pole to the top one.
+    pub fn get_sphere_uv(p: &Point) -> (f64, f64) {
+        let theta = (-p.y).clamp(-1., 1.).acos();
+        let phi = (-p.z).atan2(p.x) + std::f64::consts::PI;
+        (
+            phi / (2. * std::f64::consts::PI),
+            theta / std::f64::consts::PI,
+        )
+    }
+}
+
+/// Sphere whose center moves linearly from `center_start` to `center_end` while the shutter is
+/// open.
+#[derive(Serialize, Deserialize)]
+pub struct MovingSphere {
+    pub center_start: Point,
+    pub center_end: Point,
+    pub radius: f64,
+    pub material: Arc<Material>,
+}
+
+impl MovingSphere {
+    /// Center of the sphere at `time`, in [0,1) over the shutter interval
+    pub fn center_at(&self, time: f64) -> Point {
+        self.center_start + time * (self.center_end - self.center_start)
+    }
+
+    /// Box enclosing the sphere over its whole motion
+    pub fn bounding_box(&self) -> Aabb {
+        let at = |center: Point| {
+            Sphere {
+                center,
+                radius: self.radius,
+                material: Arc::clone(&self.material),
+            }
+            .bounding_box()
+        };
+        Aabb::merge(&at(self.center_start), &at(self.center_end))
+    }
+}
+
+/// Infinite plane going through `point`, its front side is the one `normal` points to.
+#[derive(Serialize, Deserialize)]
+pub struct Plane {
+    pub point: Point,
+    pub normal: Vec3,
+    pub material: Arc<Material>,
+}
+
+impl Plane {
+    /// The plane is infinite, so is its box
+    pub fn bounding_box(&self) -> Aabb {
+        Aabb {
+            x: Interval::UNIVERSE,
+            y: Interval::UNIVERSE,
+            z: Interval::UNIVERSE,
+        }
+    }
+}
+
+/// Parallelogram with a corner at `q` and the two edges `u` and `v` starting from it.
+/// Its front side is the one u x v points to.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Quad {
+    pub q: Point,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Arc<Material>,
+}
+
+impl Quad {
+    /// Coordinates (alpha, beta) of the point `p` of the quad plane in the (q, u, v) frame:
+    /// p = q + alpha * u + beta * v.
+    /// Points inside the quad have both coordinates in [0,1].
+    pub fn planar_coordinates(&self, p: Point) -> (f64, f64) {
+        let n = self.u.cross(&self.v);
+        let w = n / n.length_squared();
+        let planar_hit = p - self.q;
+        let alpha = w.dot(&planar_hit.cross(&self.v));
+        let beta = w.dot(&self.u.cross(&planar_hit));
+        (alpha, beta)
+    }
+
+    /// Whether `p` lies on the quad, up to rounding errors
+    pub fn contains(&self, p: Point) -> bool {
+        let n = self.u.cross(&self.v).normalized();
+        let tolerance = 1e-9 * (1. + (p - self.q).len());
+        let (alpha, beta) = self.planar_coordinates(p);
+        (p - self.q).dot(&n).abs() <= tolerance
+            && (0.0..=1.0).contains(&alpha)
+            && (0.0..=1.0).contains(&beta)
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        Aabb::merge(
+            &Aabb::from_points(self.q, self.q + self.u + self.v),
+            &Aabb::from_points(self.q + self.u, self.q + self.v),
+        )
+    }
+}
+
+/// Triangle of vertices `a`, `b` and `c`.
+/// Its front side is the one from which the vertices are seen counterclockwise. Both sides can
+/// be hit, so that closed meshes of dielectric materials can be entered and exited.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Triangle {
+    pub a: Point,
+    pub b: Point,
+    pub c: Point,
+    /// Unit normals at a, b and c, interpolated over the triangle for smooth shading. The
+    /// normal of the plane of the triangle is used everywhere when None (flat shading).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normals: Option<[Vec3; 3]>,
+    pub material: Arc<Material>,
+    /// Ignore the rays hitting the back of the triangle, the side opposite to (b - a) x (c - a).
+    /// Saves work on closed meshes, whose back faces are hidden by their front faces.
+    #[serde(default)]
+    pub cull_backfaces: bool,
+}
+
+impl Triangle {
+    pub fn bounding_box(&self) -> Aabb {
+        Aabb::merge(
+            &Aabb::from_points(self.a, self.b),
+            &Aabb::from_points(self.c, self.c),
+        )
+    }
+}
+
+/// Triangle mesh, with its own bounding volume hierarchy so that rays are only tested against
+/// the few triangles near their path, however many triangles there are.
+pub struct Mesh {
+    /// None for a mesh without triangles
+    bvh: Option<BvhNode>,
+}
+
+impl Mesh {
+    /// A mesh without triangles is never hit, and has an empty bounding box.
+    /// `cull_backfaces` replaces the one of every triangle, see `Triangle::cull_backfaces`.
+    pub fn new(triangles: Vec<Triangle>, cull_backfaces: bool) -> Mesh {
+        let triangles = triangles
+            .into_iter()
+            .map(|triangle| {
+                Arc::new(Hittable::Triangle(Triangle {
+                    cull_backfaces,
+                    ..triangle
+                }))
+            })
+            .collect::<Vec<_>>();
+        Mesh {
+            bvh: (!triangles.is_empty()).then(|| BvhNode::new(triangles)),
+        }
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        self.bvh.as_ref().map_or(Aabb::EMPTY, |bvh| bvh.bbox)
+    }
+}
+
+/// Finite cylinder standing on the disk of center `base`, extending over `height` along `axis`.
+/// Its texture coordinates go around the axis (u) and along it (v), or from the axis to the
+/// edge on the caps.
+#[derive(Serialize, Deserialize)]
+pub struct Cylinder {
+    pub base: Point,
+    /// Direction of the cylinder, doesn't need to be normalized
+    pub axis: Vec3,
+    pub radius: f64,
+    pub height: f64,
+    /// Whether the disks closing both ends are part of the surface
+    pub capped: bool,
+    pub material: Arc<Material>,
+}
+
+impl Cylinder {
+    /// Conservative box, enclosing the spheres of radius `radius` around both ends
+    pub fn bounding_box(&self) -> Aabb {
+        let radius = Vec3 {
+            x: self.radius,
+            y: self.radius,
+            z: self.radius,
+        };
+        let top = self.base + self.height * self.axis.normalized();
+        Aabb::merge(
+            &Aabb::from_points(self.base - radius, self.base + radius),
+            &Aabb::from_points(top - radius, top + radius),
+        )
+    }
+
+    /// Angle around the axis of the direction `from_axis`, perpendicular to it, in [0,1]
+    fn angle_fraction(&self, from_axis: &Vec3) -> f64 {
+        let axis = self.axis.normalized();
+        // Any direction perpendicular to the axis will do as origin of the angles
+        let helper = if axis.x.abs() > 0.9 {
+            Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            }
+        } else {
+            Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            }
+        };
+        let e1 = axis.cross(&helper).normalized();
+        let e2 = axis.cross(&e1);
+        (from_axis.dot(&e2).atan2(from_axis.dot(&e1)) + std::f64::consts::PI)
+            / (2. * std::f64::consts::PI)
+    }
+}
+
+/// Object identified by a name, reported in the records of its hits.
+#[derive(Serialize, Deserialize)]
+pub struct Named {
+    pub object: Arc<Hittable>,
+    pub name: Arc<str>,
+}
+
+/// Instance of an object moved by `offset`, to reuse its geometry at another position.
+#[derive(Serialize, Deserialize)]
+pub struct Translate {
+    pub object: Arc<Hittable>,
+    pub offset: Vec3,
+}
+
+/// Instance of an object rotated around the y axis.
+pub struct RotateY {
+    object: Arc<Hittable>,
+    sin_theta: f64,
+    cos_theta: f64,
+    bbox: Aabb,
+}
+
+impl RotateY {
+    /// Rotation of `angle` degrees, counterclockwise when looking down the y axis
+    pub fn new(object: Arc<Hittable>, angle: f64) -> RotateY {
+        let radians = angle.to_radians();
+        let object_box = object.bounding_box();
+        let mut rotate = RotateY {
+            object,
+            sin_theta: radians.sin(),
+            cos_theta: radians.cos(),
+            bbox: object_box,
+        };
+        // Box enclosing the eight rotated corners of the object's box
+        let corners = (0..8).map(|corner| {
+            let pick = |interval: Interval, bit: u32| {
+                if corner & bit == 0 {
+                    interval.min
+                } else {
+                    interval.max
+                }
+            };
+            rotate.to_world(Point {
+                x: pick(object_box.x, 1),
+                y: pick(object_box.y, 2),
+                z: pick(object_box.z, 4),
+            })
+        });
+        rotate.bbox = corners
+            .map(|corner| Aabb::from_points(corner, corner))
+            .reduce(|a, b| Aabb::merge(&a, &b))
+            .unwrap();
+        rotate
+    }
+
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.cos_theta * v.x - self.sin_theta * v.z,
+            y: v.y,
+            z: self.sin_theta * v.x + self.cos_theta * v.z,
+        }
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.cos_theta * v.x + self.sin_theta * v.z,
+            y: v.y,
+            z: -self.sin_theta * v.x + self.cos_theta * v.z,
+        }
+    }
+}
+
+/// Instance of an object stretched by `factor` along each axis, from the origin.
+/// The factors must not be zero.
+#[derive(Serialize, Deserialize)]
+pub struct Scale {
+    pub object: Arc<Hittable>,
+    pub factor: Vec3,
+}
+
+impl Scale {
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        Vec3 {
+            x: v.x / self.factor.x,
+            y: v.y / self.factor.y,
+            z: v.z / self.factor.z,
+        }
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3 {
+            x: v.x * self.factor.x,
+            y: v.y * self.factor.y,
+            z: v.z * self.factor.z,
+        }
+    }
+}
+
+/// Volume of constant density filling the inside of `boundary`, like fog or smoke. Rays may
+/// scatter anywhere inside of it, more likely the denser it is. The boundary must be convex.
+#[derive(Serialize, Deserialize)]
+pub struct ConstantMedium {
+    pub boundary: Arc<Hittable>,
+    pub density: f64,
+    /// Material scattering the rays inside of the medium, usually isotropic
+    pub phase_material: Arc<Material>,
+}
+
+/// Axis aligned bounding box, the volume delimited by an interval on each axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+impl Aabb {
+    /// Contains nothing, merging it with another box gives that other box
+    pub const EMPTY: Aabb = Aabb {
+        x: Interval::EMPTY,
+        y: Interval::EMPTY,
+        z: Interval::EMPTY,
+    };
+
+    /// Box with opposite corners `a` and `b`, in any order.
+    pub fn from_points(a: Point, b: Point) -> Aabb {
+        let axis = |a: f64, b: f64| Interval {
+            min: a.min(b),
+            max: a.max(b),
+        };
+        Aabb {
+            x: axis(a.x, b.x),
+            y: axis(a.y, b.y),
+            z: axis(a.z, b.z),
+        }
+    }
+
+    /// Same box moved by `offset`
+    pub fn offset(&self, offset: Vec3) -> Aabb {
+        let shift = |interval: Interval, delta: f64| Interval {
+            min: interval.min + delta,
+            max: interval.max + delta,
+        };
+        Aabb {
+            x: shift(self.x, offset.x),
+            y: shift(self.y, offset.y),
+            z: shift(self.z, offset.z),
+        }
+    }
+
+    /// Same box stretched by `factor` along each axis, from the origin
+    pub fn scale(&self, factor: Vec3) -> Aabb {
+        Aabb::from_points(
+            Point {
+                x: self.x.min * factor.x,
+                y: self.y.min * factor.y,
+                z: self.z.min * factor.z,
+            },
+            Point {
+                x: self.x.max * factor.x,
+                y: self.y.max * factor.y,
+                z: self.z.max * factor.z,
+            },
+        )
+    }
+
+    /// Smallest box enclosing both boxes
+    pub fn merge(a: &Aabb, b: &Aabb) -> Aabb {
+        Aabb {
+            x: Interval::enclose(a.x, b.x),
+            y: Interval::enclose(a.y, b.y),
+            z: Interval::enclose(a.z, b.z),
+        }
+    }
+
+    /// Interval of the box along the axis `n`: 0 for x, 1 for y and 2 for z
+    pub fn axis(&self, n: usize) -> Interval {
+        match n {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
+    /// Index of the axis along which the box is the widest
+    pub fn longest_axis(&self) -> usize {
+        let size = |axis: Interval| axis.max - axis.min;
+        Vec3 {
+            x: size(self.x),
+            y: size(self.y),
+            z: size(self.z),
+        }
+        .max_component()
+        .0
+    }
+
+    /// Whether the ray goes through the box for a t within `interval`.
+    pub fn hit(&self, ray: &Ray, mut interval: Interval) -> bool {
+        // Slab method: on each axis, compute the t at which the ray enters and leaves the slab
+        // between the two planes of the box. The ray goes through the box if the intervals
+        // of the three slabs overlap.
+        for n in 0..3 {
+            let axis = self.axis(n);
+            let inverse_direction = 1.0 / ray.direction[n];
+            let t0 = (axis.min - ray.origin[n]) * inverse_direction;
+            let t1 = (axis.max - ray.origin[n]) * inverse_direction;
+            let (t_enter, t_exit) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            interval.min = interval.min.max(t_enter);
+            interval.max = interval.max.min(t_exit);
+            if interval.max < interval.min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Node of a bounding volume hierarchy, a binary tree of boxes enclosing the objects of a scene.
+/// A ray only needs to be tested against the children of the nodes whose box it hits.
+pub struct BvhNode {
+    pub left: Arc<Hittable>,
+    pub right: Arc<Hittable>,
+    pub bbox: Aabb,
+}
+
+impl BvhNode {
+    /// Build the hierarchy by recursively splitting the objects in two halves along the
+    /// longest axis of their enclosing box.
+    /// Panics if there are no objects.
+    pub fn new(mut objects: Vec<Arc<Hittable>>) -> BvhNode {
+        let bbox = objects
+            .iter()
+            .map(|object| object.bounding_box())
+            .reduce(|a, b| Aabb::merge(&a, &b))
+            .expect("A BVH node needs at least one object");
+        let axis = bbox.longest_axis();
+
+        let (left, right) = match objects.len() {
+            1 => (Arc::clone(&objects[0]), Arc::clone(&objects[0])),
+            2 => (Arc::clone(&objects[0]), Arc::clone(&objects[1])),
+            _ => {
+                objects.sort_by(|a, b| {
+                    let a_min = a.bounding_box().axis(axis).min;
+                    let b_min = b.bounding_box().axis(axis).min;
+                    a_min.total_cmp(&b_min)
+                });
+                let right_objects = objects.split_off(objects.len() / 2);
+                (
+                    Arc::new(Hittable::Bvh(BvhNode::new(objects))),
+                    Arc::new(Hittable::Bvh(BvhNode::new(right_objects))),
+                )
+            }
+        };
+        BvhNode { left, right, bbox }
+    }
+}
+
+/// Emissive quad whose light is sampled directly by the camera, see `World::add_light`.
+/// Stored in scene files as the fields of its quad, next to its `shadow_softness`, 1 when
+/// omitted.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AreaLight {
+    #[serde(flatten)]
+    pub quad: Quad,
+    /// Share of each edge of the quad that shadow rays are aimed at, around its center.
+    /// 1 samples the whole light, 0 always aims at its center and gives hard shadows.
+    /// The amount of light received doesn't depend on it.
+    #[serde(default = "AreaLight::default_shadow_softness")]
+    pub shadow_softness: f64,
+}
+
+impl AreaLight {
+    fn default_shadow_softness() -> f64 {
+        1.
+    }
+
+    pub fn center(&self) -> Point {
+        self.quad.q + 0.5 * (self.quad.u + self.quad.v)
+    }
+
+    /// Random point on the light, to cast a shadow ray toward.
+    /// The samples are spread around the center of the light, scaled by `shadow_softness`.
+    pub fn sample_point(&self) -> Point {
+        // Offsets in [-0.5,0.5[ along each edge, relative to the center
+        let offset_u = utils::random::<f64>() - 0.5;
+        let offset_v = utils::random::<f64>() - 0.5;
+        self.center() + self.shadow_softness * (offset_u * self.quad.u + offset_v * self.quad.v)
+    }
+}
+
+/// Axis aligned box, made of six quads facing outward.
+pub struct BoxPrim {
+    sides: World,
+    bbox: Aabb,
+}
+
+impl BoxPrim {
+    /// Box between the two opposite corners `a` and `b`
+    pub fn new(a: Point, b: Point, material: Arc<Material>) -> BoxPrim {
+        let bbox = Aabb::from_points(a, b);
+        let min = Point {
+            x: bbox.x.min,
+            y: bbox.y.min,
+            z: bbox.z.min,
+        };
+        let max = Point {
+            x: bbox.x.max,
+            y: bbox.y.max,
+            z: bbox.z.max,
+        };
+        let dx = Vec3 {
+            x: max.x - min.x,
+            y: 0.,
+            z: 0.,
+        };
+        let dy = Vec3 {
+            x: 0.,
+            y: max.y - min.y,
+            z: 0.,
+        };
+        let dz = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: max.z - min.z,
+        };
+        // Corner and edges of each face, ordered so that u x v points outside of the box
+        let faces = [
+            (Point { z: max.z, ..min }, dx, dy),
+            (Point { y: min.y, ..max }, -dz, dy),
+            (Point { x: max.x, ..min }, -dx, dy),
+            (min, dz, dy),
+            (Point { x: min.x, ..max }, dx, -dz),
+            (min, dx, dz),
+        ];
+        let objects = faces
+            .into_iter()
+            .map(|(q, u, v)| {
+                Arc::new(Hittable::Quad(Quad {
+                    q,
+                    u,
+                    v,
+                    material: Arc::clone(&material),
+                }))
+            })
+            .collect();
+        BoxPrim {
+            sides: World {
+                objects,
+                lights: vec![],
+            },
+            bbox,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct World {
+    pub objects: Vec<Arc<Hittable>>,
+    /// Emissive quads, also among the objects, toward which light can be sampled directly
+    pub lights: Vec<AreaLight>,
+}
+
+impl World {
+    /// Empty world, to be filled with `add`
+    pub fn new() -> World {
+        World::default()
+    }
+
+    /// Add an object to the world, any kind of geometry can be mixed.
+    /// Returns the world so that additions can be chained.
+    pub fn add(&mut self, object: Hittable) -> &mut World {
+        self.objects.push(Arc::new(object));
+        self
+    }
+
+    /// Whether `p` lies on one of the lights sampled directly
+    pub fn lights_contain(&self, p: Point) -> bool {
+        self.lights.iter().any(|light| light.quad.contains(p))
+    }
+
+    /// Add an emissive quad, both as an object and as a light to sample directly.
+    pub fn add_light(&mut self, light: AreaLight) -> &mut World {
+        let quad = light.quad.clone();
+        self.lights.push(light);
+        self.add(Hittable::Quad(quad))
+    }
+
+    pub fn hit(&self, ray: &Ray, mut interval: Interval) -> Option<HitRecord> {
+        let mut closest_hit: Option<HitRecord> = None;
+
+        for object in &self.objects {
+            if let Some(hit) = object.hit(ray, interval) {
+                interval.max = hit.t;
+                closest_hit = Some(hit);
+            }
+        }
+
+        closest_hit
+    }
+
+    /// Gather the objects of the world in a bounding volume hierarchy, so that rays are only
+    /// tested against the objects they may hit instead of every object.
+    pub fn build_bvh(self) -> World {
+        if self.objects.is_empty() {
+            return self;
+        }
+        World {
+            objects: vec![Arc::new(Hittable::Bvh(BvhNode::new(self.objects)))],
+            lights: self.lights,
+        }
+    }
+
+    /// Final scene of Ray Tracing in One Weekend: three large spheres on a ground surrounded by
+    /// a grid of small spheres of random materials. The same seed always gives the same scene.
+    pub fn random_scene(seed: u64) -> World {
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point {
+                x: 0.,
+                y: -1000.,
+                z: 0.,
+            },
+            radius: 1000.,
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::from([0.5, 0.5, 0.5])),
+            }),
+        }));
+
+        let heroes = [
+            (
+                -4.,
+                Material {
+                    material_type: MaterialType::Lambertian(Lambertian),
+                    albedo: Texture::Solid(Color::from([0.4, 0.2, 0.1])),
+                },
+            ),
+            (
+                0.,
+                Material {
+                    material_type: MaterialType::Dielectric(Dielectric {
+                        refraction_index: 1.5,
+                        absorption: Color3::black(),
+                    }),
+                    albedo: Texture::Solid(Color::white()),
+                },
+            ),
+            (
+                4.,
+                Material {
+                    material_type: MaterialType::Metal(Metal { fuzz: 0. }),
+                    albedo: Texture::Solid(Color::from([0.7, 0.6, 0.5])),
+                },
+            ),
+        ];
+
+        let small_radius = 0.2;
+        for a in -11..11 {
+            for b in -11..11 {
+                // Jitter is drawn from its own sequence, unrelated to the one of the material
+                let mut rng = StdRng::seed_from_u64(utils::hash_seed(!seed, a as u64, b as u64));
+                let center = Point {
+                    x: a as f64 + 0.9 * rng.gen::<f64>(),
+                    y: small_radius,
+                    z: b as f64 + 0.9 * rng.gen::<f64>(),
+                };
+                // Leave room around the large spheres
+                let too_close = heroes.iter().any(|(x, _)| {
+                    let hero_base = Point {
+                        x: *x,
+                        y: small_radius,
+                        z: 0.,
+                    };
+                    (center - hero_base).length_squared() <= 0.9 * 0.9
+                });
+                if !too_close {
+                    world.add(Hittable::Sphere(Sphere {
+                        center,
+                        radius: small_radius,
+                        material: Arc::new(material_from_seed(a, b, seed)),
+                    }));
+                }
+            }
+        }
+
+        for (x, material) in heroes {
+            world.add(Hittable::Sphere(Sphere {
+                center: Point { x, y: 1., z: 0. },
+                radius: 1.,
+                material: Arc::new(material),
+            }));
+        }
+        world
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vec3_cross() {
+        let axis = |x: f64, y: f64, z: f64| Vec3 { x, y, z };
+        let (x_hat, y_hat, z_hat) = (axis(1., 0., 0.), axis(0., 1., 0.), axis(0., 0., 1.));
+        assert_eq!(x_hat.cross(&y_hat), z_hat);
+        assert_eq!(y_hat.cross(&z_hat), x_hat);
+        assert_eq!(z_hat.cross(&x_hat), y_hat);
+        assert_eq!(y_hat.cross(&x_hat), -z_hat);
+
+        let v = axis(1., -2., 3.);
+        assert_eq!(v.cross(&v), axis(0., 0., 0.));
+        // Perpendicular to both vectors
+        let w = axis(4., 0.5, -1.);
+        assert_eq!(v.cross(&w).dot(&v), 0.);
+        assert_eq!(v.cross(&w).dot(&w), 0.);
+    }
+
+    #[test]
+    fn vec3_neg() {
+        let v = Vec3 {
+            x: 1.,
+            y: -2.,
+            z: 3.,
+        };
+        assert_eq!(
+            -v,
+            Vec3 {
+                x: -1.,
+                y: 2.,
+                z: -3.
+            }
+        );
+    }
+
+    #[test]
+    fn vec3_assign_operators() {
+        let mut v = Vec3 {
+            x: 1.,
+            y: -2.,
+            z: 3.,
+        };
+        let w = Vec3 {
+            x: 0.5,
+            y: 1.,
+            z: -1.,
+        };
+        v += w;
+        assert_eq!(
+            v,
+            Vec3 {
+                x: 1.5,
+                y: -1.,
+                z: 2.
+            }
+        );
+        v -= w;
+        v -= w;
+        assert_eq!(
+            v,
+            Vec3 {
+                x: 0.5,
+                y: -3.,
+                z: 4.
+            }
+        );
+        v *= 2.;
+        assert_eq!(
+            v,
+            Vec3 {
+                x: 1.,
+                y: -6.,
+                z: 8.
+            }
+        );
+    }
+
+    #[test]
+    fn vec3_reflect() {
+        let normal = Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        };
+        let incoming = Vec3 {
+            x: 1.,
+            y: -1.,
+            z: 0.,
+        };
+        assert_eq!(
+            incoming.reflect(&normal),
+            Vec3 {
+                x: 1.,
+                y: 1.,
+                z: 0.
+            }
+        );
+    }
+
+    #[test]
+    fn vec3_refract() {
+        let normal = Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        };
+        // 30° incidence, from air into glass
+        let incoming = Vec3 {
+            x: 0.5,
+            y: -(3.0_f64.sqrt()) / 2.,
+            z: 0.,
+        };
+        let refracted = incoming.refract(&normal, 1. / 1.5);
+        assert!((refracted.len() - 1.).abs() < 1e-9);
+        // Snell's law: sin(refracted) = sin(incident) / 1.5
+        assert!((refracted.x - 0.5 / 1.5).abs() < 1e-9);
+        assert!(refracted.y < 0.);
+        // Head on, the ray isn't bent
+        assert_eq!(
+            (-normal).refract(&normal, 1. / 1.5),
+            Vec3 {
+                x: 0.,
+                y: -1.,
+                z: 0.
+            }
+        );
+    }
+
+    #[test]
+    fn vec3_normalized() {
+        let v = Vec3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        };
+        assert_eq!(
+            v.normalized(),
+            Vec3 {
+                x: 1.0 / 3.0_f64.sqrt(),
+                y: 1.0 / 3.0_f64.sqrt(),
+                z: 1.0 / 3.0_f64.sqrt(),
+            }
+        );
+    }
+
+    #[test]
+    fn vec3_len() {
+        let v = Vec3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        };
+        assert_eq!(v.len(), 3.0_f64.sqrt())
+    }
+
+    #[test]
+    fn vec3_length_squared() {
+        let vectors = [
+            Vec3 {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+            Vec3 {
+                x: 3.,
+                y: -4.,
+                z: 0.,
+            },
+            Vec3 {
+                x: 0.5,
+                y: 2.,
+                z: -7.25,
+            },
+        ];
+        for v in vectors {
+            assert!((v.length_squared() - v.len() * v.len()).abs() < 1e-12);
+        }
+        assert_eq!(vectors[1].length_squared(), 25.);
+    }
+
+    #[test]
+    fn vec3_abs() {
+        let v = Vec3 {
+            x: -1.,
+            y: 2.,
+            z: -3.,
+        };
+        assert_eq!(
+            v.abs(),
+            Vec3 {
+                x: 1.,
+                y: 2.,
+                z: 3.
+            }
+        );
+    }
+
+    #[test]
+    fn vec3_signum() {
+        let v = Vec3 {
+            x: -1.,
+            y: 0.,
+            z: 3.,
+        };
+        assert_eq!(
+            v.signum(),
+            Vec3 {
+                x: -1.,
+                y: 0.,
+                z: 1.
+            }
+        );
+    }
+
+    #[test]
+    fn vec3_min_max_component() {
+        let v = Vec3 {
+            x: 1.,
+            y: 5.,
+            z: 3.,
+        };
+        assert_eq!(v.max_component(), (1, 5.));
+        assert_eq!(v.min_component(), (0, 1.));
+        let v = Vec3 {
+            x: 2.,
+            y: -4.,
+            z: 7.,
+        };
+        assert_eq!(v.max_component(), (2, 7.));
+        assert_eq!(v.min_component(), (1, -4.));
+    }
+
+    #[test]
+    fn vec3_near_zero() {
+        let tiny = Vec3 {
+            x: 1e-9,
+            y: -1e-9,
+            z: 0.,
+        };
+        assert!(tiny.near_zero());
+        let large_negative = Vec3 {
+            x: -5.,
+            y: -5.,
+            z: -5.,
+        };
+        assert!(!large_negative.near_zero());
+        let one_large_component = Vec3 {
+            x: 1e-9,
+            y: 1e-9,
+            z: -1.,
+        };
+        assert!(!one_large_component.near_zero());
+    }
+
+    #[test]
+    fn random_unit_vector_is_centered() {
+        let samples = 100_000;
+        let mut sum = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        };
+        for _ in 0..samples {
+            let v = Vec3::random_unit_vector();
+            assert!((v.len() - 1.0).abs() < 1e-9);
+            sum += v;
+        }
+        let mean = sum / samples as f64;
+        assert!(mean.x.abs() < 0.02);
+        assert!(mean.y.abs() < 0.02);
+        assert!(mean.z.abs() < 0.02);
+    }
+
+    #[test]
+    fn vec3_constructors() {
+        assert_eq!(Vec3::new(1., 2., 3.), Vec3::from([1.0, 2.0, 3.0]));
+        assert_eq!(
+            Vec3::new(1., 2., 3.),
+            Vec3 {
+                x: 1.,
+                y: 2.,
+                z: 3.
+            }
+        );
+        assert_eq!(Vec3::ZERO, Vec3::from([0.; 3]));
+        assert_eq!(Vec3::ONE * 2., Vec3::new(2., 2., 2.));
+    }
+
+    #[test]
+    fn random_unit_vector_is_finite() {
+        for seed in 0..20 {
+            utils::seed_random(seed);
+            for _ in 0..10_000 {
+                let v = Vec3::random_unit_vector();
+                assert!(v.x.is_finite() && v.y.is_finite() && v.z.is_finite());
+                assert!((v.len() - 1.0).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn hit_sphere() {
+        let material_test = Arc::new(Material {
+            material_type: MaterialType::Lambertian(Lambertian),
+            albedo: Texture::Solid(Color::from([0.9, 0.9, 0.9])),
+        });
+        let sphere = Sphere {
+            radius: 1.0,
+            center: Point {
+                x: 3.,
+                y: 0.,
+                z: 0.,
+            },
+            material: Arc::clone(&material_test),
+        };
+        let ray_should_hit = Ray {
+            origin: Point {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 1.0,
+                y: 0.,
+                z: 0.,
+            },
+            time: 0.,
+        };
+        assert_eq!(
+            Hittable::hit(
+                &Hittable::Sphere(sphere),
+                &ray_should_hit,
+                Interval { min: 0., max: 100. }
+            ),
+            Some(HitRecord {
+                p: Vec3 {
+                    x: 2.,
+                    y: 0.,
+                    z: 0.
+                },
+                normal: Vec3 {
+                    x: -1.,
+                    y: 0.,
+                    z: 0.
+                },
+                t: 2.,
+                front_face: true,
+                material: Arc::clone(&material_test),
+                u: 0.,
+                v: 0.5,
+                name: None,
+            })
+        )
+    }
+
+    #[test]
+    fn moving_sphere_follows_time() {
+        let sphere = Hittable::MovingSphere(MovingSphere {
+            center_start: Point {
+                x: 3.,
+                y: 0.,
+                z: 0.,
+            },
+            center_end: Point {
+                x: 3.,
+                y: 0.,
+                z: 4.,
+            },
+            radius: 1.,
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        });
+        let ray_at = |z: f64, time: f64| Ray {
+            origin: Point { x: 0., y: 0., z },
+            direction: Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            time,
+        };
+        let interval = Interval { min: 0., max: 100. };
+        assert!(sphere.hit(&ray_at(0., 0.), interval).is_some());
+        assert!(sphere.hit(&ray_at(0., 0.9), interval).is_none());
+        assert!(sphere.hit(&ray_at(4., 0.9), interval).is_some());
+        assert!(sphere.hit(&ray_at(2., 0.5), interval).is_some());
+        // The box covers the whole motion
+        let bbox = sphere.bounding_box();
+        assert_eq!(bbox.z, Interval { min: -1., max: 5. });
+        assert_eq!(bbox.x, Interval { min: 2., max: 4. });
+    }
+
+    #[test]
+    fn box_near_face_hit() {
+        let material = Arc::new(Material {
+            material_type: MaterialType::Lambertian(Lambertian),
+            albedo: Texture::Solid(Color::white()),
+        });
+        let cube = Hittable::Box(BoxPrim::new(
+            Point {
+                x: 3.,
+                y: 0.5,
+                z: 0.5,
+            },
+            Point {
+                x: 2.,
+                y: -0.5,
+                z: -0.5,
+            },
+            material,
+        ));
+        let hit = cube
+            .hit(&ray_along_x(0., 0.), Interval { min: 0., max: 10. })
+            .unwrap();
+        assert!((hit.t - 7.).abs() < 1e-9);
+        assert!(hit.front_face);
+        assert_eq!(
+            hit.normal,
+            Vec3 {
+                x: -1.,
+                y: 0.,
+                z: 0.
+            }
+        );
+        assert_eq!(cube.bounding_box().x, Interval { min: 2., max: 3. });
+
+        // Every face points outside of the box
+        let center = Point {
+            x: 2.5,
+            y: 0.,
+            z: 0.,
+        };
+        let Hittable::Box(prim) = &cube else {
+            unreachable!()
+        };
+        for side in &prim.sides.objects {
+            let Hittable::Quad(quad) = side.as_ref() else {
+                unreachable!()
+            };
+            let face_center = quad.q + 0.5 * quad.u + 0.5 * quad.v;
+            assert!(quad.u.cross(&quad.v).dot(&(face_center - center)) > 0.);
+        }
+    }
+
+    #[test]
+    fn translated_sphere() {
+        let sphere = Arc::new(Hittable::Sphere(Sphere {
+            center: Point {
+                x: 3.,
+                y: 0.,
+                z: 0.,
+            },
+            radius: 1.,
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        }));
+        let translated = Hittable::Translate(Translate {
+            object: Arc::clone(&sphere),
+            offset: Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 2.,
+            },
+        });
+        let interval = Interval { min: 0., max: 100. };
+        // The original position is now empty
+        assert!(translated.hit(&ray_along_x(0., 0.), interval).is_none());
+        let hit = translated.hit(&ray_along_x(0., 2.), interval).unwrap();
+        let original = sphere.hit(&ray_along_x(0., 0.), interval).unwrap();
+        assert!((hit.t - original.t - 1.).abs() < 1e-9);
+        assert_eq!(
+            hit.p,
+            Point {
+                x: 3.,
+                y: 0.,
+                z: 2.
+            }
+        );
+        assert_eq!(hit.normal, original.normal);
+        assert_eq!(translated.bounding_box().z, Interval { min: 1., max: 3. });
+    }
+
+    #[test]
+    fn rotated_box() {
+        let material = Arc::new(Material {
+            material_type: MaterialType::Lambertian(Lambertian),
+            albedo: Texture::Solid(Color::white()),
+        });
+        // Long along x, thin along z
+        let slab = Arc::new(Hittable::Box(BoxPrim::new(
+            Point {
+                x: -1.,
+                y: -1.,
+                z: -0.25,
+            },
+            Point {
+                x: 1.,
+                y: 1.,
+                z: 0.25,
+            },
+            material,
+        )));
+        let interval = Interval { min: 0., max: 100. };
+        let ray = ray_along_x(0., 0.);
+        assert!((slab.hit(&ray, interval).unwrap().t - 4.).abs() < 1e-9);
+
+        // Once rotated, the ray hits the thin side instead of the end
+        let rotated = Hittable::RotateY(RotateY::new(slab, 90.));
+        let hit = rotated.hit(&ray, interval).unwrap();
+        assert!((hit.t - 4.75).abs() < 1e-9);
+        assert!((hit.p.x + 0.25).abs() < 1e-9);
+        assert!((hit.normal.x + 1.).abs() < 1e-9);
+        assert!(hit.normal.z.abs() < 1e-9);
+        assert!(hit.front_face);
+        // An offset ray missing the end now hits the side
+        assert!(rotated.hit(&ray_along_x(0., 0.75), interval).is_some());
+
+        let bbox = rotated.bounding_box();
+        assert!((bbox.x.max - 0.25).abs() < 1e-9);
+        assert!((bbox.z.max - 1.).abs() < 1e-9);
+    }
+
+    /// Fraction of the rays crossing a medium of the given density through a unit sphere's
+    /// diameter that scatter inside of it
+    fn medium_opacity(density: f64) -> f64 {
+        let medium = Hittable::ConstantMedium(ConstantMedium {
+            boundary: Arc::new(glass_sphere()),
+            density,
+            phase_material: Arc::new(Material {
+                material_type: MaterialType::Isotropic(Isotropic),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        });
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        let rays = 1000;
+        let hits = (0..rays)
+            .filter_map(|_| medium.hit(&ray_along_x(0., 0.), interval))
+            .inspect(|hit| assert!((2.0..=4.0).contains(&hit.p.x)))
+            .count();
+        hits as f64 / rays as f64
+    }
+
+    #[test]
+    fn constant_medium_opacity() {
+        // Expected opacities are 1 - exp(-2 * density)
+        assert!(medium_opacity(0.01) < 0.1);
+        assert!(medium_opacity(10.) > 0.95);
+    }
+
+    #[test]
+    fn isotropic_scatter_ignores_normal() {
+        // The counts are checked against fixed bounds, the same directions are drawn every run
+        utils::seed_random(11);
+        let hit = glass_sphere()
+            .hit(&ray_along_x(0., 0.), Interval { min: 0., max: 10. })
+            .unwrap();
+        let hit = HitRecord {
+            material: Arc::new(Material {
+                material_type: MaterialType::Isotropic(Isotropic),
+                albedo: Texture::Solid(Color::white()),
+            }),
+            ..hit
+        };
+        let backward = (0..1000)
+            .filter(|_| {
+                let scattered = ScatteredRay::scatter(&hit, &ray_along_x(0., 0.)).remove(0);
+                scattered.ray.direction.dot(&hit.normal) < 0.
+            })
+            .count();
+        assert!(backward > 300 && backward < 700);
+
+        // Uniform over the sphere: each octant gets about an eighth of the unit directions
+        let mut octants = [0; 8];
+        for _ in 0..8000 {
+            let direction = ScatteredRay::scatter(&hit, &ray_along_x(0., 0.))
+                .remove(0)
+                .ray
+                .direction;
+            assert!((direction.len() - 1.).abs() < 1e-9);
+            let octant = (direction.x > 0.) as usize
+                | ((direction.y > 0.) as usize) << 1
+                | ((direction.z > 0.) as usize) << 2;
+            octants[octant] += 1;
+        }
+        assert!(octants.iter().all(|&count| count > 850 && count < 1150));
+    }
+
+    #[test]
+    fn world_mixing_geometries() {
+        let material = Arc::new(Material {
+            material_type: MaterialType::Lambertian(Lambertian),
+            albedo: Texture::Solid(Color::white()),
+        });
+        let mut world = World::new();
+        world.add(Hittable::Sphere(Sphere {
+            center: Point {
+                x: 3.,
+                y: 0.,
+                z: 0.,
+            },
+            radius: 1.,
+            material: Arc::clone(&material),
+        }));
+        // Wall behind the sphere, facing the camera
+        world.add(Hittable::Plane(Plane {
+            point: Point {
+                x: 10.,
+                y: 0.,
+                z: 0.,
+            },
+            normal: Vec3 {
+                x: -1.,
+                y: 0.,
+                z: 0.,
+            },
+            material,
+        }));
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        let sphere_hit = world.hit(&ray_along_x(0., 0.), interval).unwrap();
+        assert!((sphere_hit.p.x - 2.).abs() < 1e-9);
+        let plane_hit = world.hit(&ray_along_x(0., 3.), interval).unwrap();
+        assert!((plane_hit.p.x - 10.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn world_built_by_chaining() {
+        let material = Arc::new(Material {
+            material_type: MaterialType::Lambertian(Lambertian),
+            albedo: Texture::Solid(Color::white()),
+        });
+        let sphere = |z: f64| {
+            Hittable::Sphere(Sphere {
+                center: Point { x: 3., y: 0., z },
+                radius: 1.,
+                material: Arc::clone(&material),
+            })
+        };
+        let mut world = World::new();
+        assert!(world.objects.is_empty());
+        world.add(sphere(-3.)).add(sphere(3.));
+        assert_eq!(world.objects.len(), 2);
+
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        let hit = world.hit(&ray_along_x(0., 3.), interval).unwrap();
+        assert!((hit.p.x - 2.).abs() < 1e-9);
+        assert!(world.hit(&ray_along_x(0., 0.), interval).is_none());
+    }
+
+    fn sphere_centers(world: &World) -> Vec<Point> {
+        world
+            .objects
+            .iter()
+            .map(|object| match object.as_ref() {
+                Hittable::Sphere(sphere) => sphere.center,
+                _ => panic!("The random scene only has spheres"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_scene_is_seeded() {
+        let scene = World::random_scene(3);
+        assert_eq!(
+            sphere_centers(&scene),
+            sphere_centers(&World::random_scene(3))
+        );
+        assert_ne!(
+            sphere_centers(&scene),
+            sphere_centers(&World::random_scene(4))
+        );
+        // Ground, large spheres and most of the 22 x 22 grid
+        assert!(scene.objects.len() > 400 && scene.objects.len() <= 4 + 22 * 22);
+    }
+
+    #[test]
+    fn random_scene_small_spheres_avoid_large_ones() {
+        let scene = World::random_scene(3);
+        let spheres: Vec<&Sphere> = scene
+            .objects
+            .iter()
+            .map(|object| match object.as_ref() {
+                Hittable::Sphere(sphere) => sphere,
+                _ => unreachable!(),
+            })
+            .collect();
+        let (large, small): (Vec<&Sphere>, Vec<&Sphere>) =
+            spheres.iter().partition(|sphere| sphere.radius >= 1.);
+        // Ground and the three large spheres
+        assert_eq!(large.len(), 4);
+        for small in small {
+            for large in &large {
+                assert!((small.center - large.center).len() > small.radius + large.radius);
+            }
+        }
+    }
+
+    #[test]
+    fn negative_radius_sphere_faces_inward() {
+        let material = Arc::new(Material {
+            material_type: MaterialType::Lambertian(Lambertian),
+            albedo: Texture::Solid(Color::white()),
+        });
+        let sphere = |radius: f64| {
+            Hittable::Sphere(Sphere {
+                center: Point {
+                    x: 3.,
+                    y: 0.,
                     z: 0.,
                 },
-                radius: 100.,
-                material: Rc::clone(&material_ground),
-            })),
-            Rc::new(Hittable::Sphere(Sphere {
+                radius,
+                material: Arc::clone(&material),
+            })
+        };
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        let outside = sphere(0.5).hit(&ray_along_x(0., 0.), interval).unwrap();
+        let hit = sphere(-0.5).hit(&ray_along_x(0., 0.), interval).unwrap();
+        assert_eq!(hit.p, outside.p);
+        assert_eq!((hit.u, hit.v), (outside.u, outside.v));
+        // Coming from outside, the ray hits the back of the inward facing surface
+        assert!(!hit.front_face);
+        // The normal still points against the ray
+        assert_eq!(
+            hit.normal,
+            Vec3 {
+                x: -1.,
+                y: 0.,
+                z: 0.
+            }
+        );
+
+        // From the center, the ray hits the front of the surface
+        let from_center = Ray {
+            origin: Point {
+                x: 3.,
+                y: 0.,
+                z: 0.,
+            },
+            ..ray_along_x(0., 0.)
+        };
+        let hit = sphere(-0.5).hit(&from_center, interval).unwrap();
+        assert!(hit.front_face);
+        assert!((hit.p.x - 3.5).abs() < 1e-9);
+        assert_eq!(hit.normal.x, -1.);
+    }
+
+    #[test]
+    fn tangent_frame_is_orthonormal() {
+        let normals = [
+            Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            Vec3 {
+                x: 0.,
+                y: -1.,
+                z: 0.,
+            },
+            Vec3 {
+                x: 1.,
+                y: 2.,
+                z: -3.,
+            }
+            .normalized(),
+        ];
+        for normal in normals {
+            let (tangent, bitangent) = normal.tangent_frame();
+            assert!((tangent.len() - 1.).abs() < 1e-9);
+            assert!((bitangent.len() - 1.).abs() < 1e-9);
+            assert!(tangent.dot(&normal).abs() < 1e-9);
+            assert!(bitangent.dot(&normal).abs() < 1e-9);
+            assert!(tangent.dot(&bitangent).abs() < 1e-9);
+            assert!((tangent.cross(&bitangent) - normal).len() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn anisotropic_metal_spreads_along_high_fuzz_axis() {
+        let sphere = Hittable::Sphere(Sphere {
+            center: Point {
+                x: 3.,
+                y: 0.,
+                z: 0.,
+            },
+            radius: 1.,
+            material: Arc::new(Material {
+                material_type: MaterialType::AnisotropicMetal(AnisotropicMetal {
+                    fuzz_u: 0.8,
+                    fuzz_v: 0.05,
+                }),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        });
+        let ray = ray_along_x(0., 0.);
+        let hit = sphere.hit(&ray, Interval { min: 0., max: 10. }).unwrap();
+        let (tangent, bitangent) = hit.normal.tangent_frame();
+        let (mut spread_u, mut spread_v) = (0., 0.);
+        for _ in 0..1000 {
+            let direction = ScatteredRay::scatter(&hit, &ray).remove(0).ray.direction;
+            assert!(direction.dot(&hit.normal) >= 0.);
+            spread_u += direction.dot(&tangent).abs();
+            spread_v += direction.dot(&bitangent).abs();
+        }
+        assert!(spread_u > 5. * spread_v);
+    }
+
+    #[test]
+    fn tinted_glass_absorbs_with_thickness() {
+        let exit_attenuation = |radius: f64| {
+            let sphere = Hittable::Sphere(Sphere {
                 center: Point {
-                    x: 1.2,
+                    x: 0.,
                     y: 0.,
                     z: 0.,
                 },
-                radius: 0.5,
-                material: Rc::clone(&material_center),
-            })),
-            Rc::new(Hittable::Sphere(Sphere {
-                center: Point {
-                    x: 1.2,
+                radius,
+                material: Arc::new(Material {
+                    material_type: MaterialType::Dielectric(Dielectric {
+                        refraction_index: 1.5,
+                        absorption: Color3 {
+                            r: 0.1,
+                            g: 0.5,
+                            b: 1.,
+                        },
+                    }),
+                    albedo: Texture::Solid(Color::white()),
+                }),
+            });
+            // From the center, the ray travels `radius` inside the glass
+            let ray = Ray {
+                origin: Point {
+                    x: 0.,
                     y: 0.,
-                    z: -1.,
+                    z: 0.,
+                },
+                ..ray_along_x(0., 0.)
+            };
+            let exit = sphere.hit(&ray, Interval { min: 0., max: 10. }).unwrap();
+            assert!(!exit.front_face);
+            ScatteredRay::scatter(&exit, &ray).remove(0).attenuation
+        };
+        let thin = exit_attenuation(0.5);
+        let thick = exit_attenuation(2.);
+        let expected = Color3 {
+            r: (-0.05_f64).exp(),
+            g: (-0.25_f64).exp(),
+            b: (-0.5_f64).exp(),
+        };
+        assert!((thin.r - expected.r).abs() < 1e-12);
+        assert!((thin.g - expected.g).abs() < 1e-12);
+        assert!((thin.b - expected.b).abs() < 1e-12);
+        assert!(thick.r < thin.r && thick.g < thin.g && thick.b < thin.b);
+        // The most absorbed channel darkens the most
+        assert!(thick.b < thick.g && thick.g < thick.r);
+    }
+
+    fn vertical_cylinder(capped: bool) -> Hittable {
+        Hittable::Cylinder(Cylinder {
+            base: Point {
+                x: 3.,
+                y: -1.,
+                z: 0.,
+            },
+            axis: Vec3 {
+                x: 0.,
+                y: 2.,
+                z: 0.,
+            },
+            radius: 0.5,
+            height: 2.,
+            capped,
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        })
+    }
+
+    #[test]
+    fn cylinder_side_hit() {
+        let cylinder = vertical_cylinder(false);
+        let interval = Interval { min: 0., max: 100. };
+        let hit = cylinder.hit(&ray_along_x(0.5, 0.), interval).unwrap();
+        assert!((hit.t - 7.5).abs() < 1e-9);
+        assert!((hit.p.x - 2.5).abs() < 1e-9);
+        assert!((hit.normal.x + 1.).abs() < 1e-9);
+        assert!(hit.normal.y.abs() < 1e-9);
+        assert!(hit.front_face);
+        assert!((hit.v - 0.75).abs() < 1e-9);
+        // Above the top, and beside the cylinder
+        assert!(cylinder.hit(&ray_along_x(1.5, 0.), interval).is_none());
+        assert!(cylinder.hit(&ray_along_x(0., 0.6), interval).is_none());
+        assert_eq!(
+            cylinder.bounding_box().y,
+            Interval {
+                min: -1.5,
+                max: 1.5
+            }
+        );
+    }
+
+    #[test]
+    fn cylinder_caps() {
+        let down = |capped: bool| {
+            let ray = Ray {
+                origin: Point {
+                    x: 3.2,
+                    y: 5.,
+                    z: 0.,
+                },
+                direction: Vec3 {
+                    x: 0.,
+                    y: -1.,
+                    z: 0.,
+                },
+                time: 0.,
+            };
+            vertical_cylinder(capped).hit(&ray, Interval { min: 0., max: 100. })
+        };
+        // Going down the axis, the open cylinder can't be hit
+        assert_eq!(down(false), None);
+        let hit = down(true).unwrap();
+        assert!((hit.t - 4.).abs() < 1e-9);
+        assert!((hit.normal.y - 1.).abs() < 1e-9);
+        assert!(hit.front_face);
+    }
+
+    #[test]
+    fn hits_exclude_interval_bounds() {
+        // The glass sphere is crossed from t = 7 to t = 9 along this ray
+        let sphere = glass_sphere();
+        let ray = ray_along_x(0., 0.);
+        let near = |min: f64, max: f64| sphere.hit(&ray, Interval { min, max }).map(|hit| hit.t);
+        assert_eq!(near(0., 7.), None);
+        assert_eq!(near(0., 7.5), Some(7.));
+        // Starting exactly on the near side, the far side is found instead
+        assert_eq!(near(7., 100.), Some(9.));
+    }
+
+    #[test]
+    fn sphere_uv_coordinates() {
+        let uv = |x: f64, y: f64, z: f64| Sphere::get_sphere_uv(&Vec3 { x, y, z });
+        assert_eq!(uv(1., 0., 0.), (0.5, 0.5));
+        assert_eq!(uv(0., 0., 1.), (0.25, 0.5));
+        // Poles
+        assert_eq!(uv(0., 1., 0.).1, 1.);
+        assert_eq!(uv(0., -1., 0.).1, 0.);
+    }
+
+    #[test]
+    fn sphere_hit_records_uv() {
+        let sphere = Hittable::Sphere(Sphere {
+            center: Point {
+                x: 3.,
+                y: 0.,
+                z: 0.,
+            },
+            radius: 2.,
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        });
+        let interval = Interval { min: 0., max: 100. };
+        // Point facing +x
+        let toward_minus_x = Ray {
+            origin: Point {
+                x: 10.,
+                y: 0.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: -1.,
+                y: 0.,
+                z: 0.,
+            },
+            time: 0.,
+        };
+        let hit = sphere.hit(&toward_minus_x, interval).unwrap();
+        assert!((hit.u - 0.5).abs() < 1e-9 && (hit.v - 0.5).abs() < 1e-9);
+        // Top pole
+        let downward = Ray {
+            origin: Point {
+                x: 3.,
+                y: 10.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: -1.,
+                z: 0.,
+            },
+            time: 0.,
+        };
+        let hit = sphere.hit(&downward, interval).unwrap();
+        assert!((hit.v - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn area_light_shadow_softness() {
+        let hard_light = AreaLight {
+            quad: Quad {
+                q: Point::new(0., 2., 0.),
+                ..unit_quad()
+            },
+            shadow_softness: 0.,
+        };
+        for _ in 0..100 {
+            assert_eq!(hard_light.sample_point(), hard_light.center());
+        }
+
+        let soft_light = AreaLight {
+            shadow_softness: 1.,
+            ..hard_light
+        };
+        let samples: Vec<Point> = (0..100).map(|_| soft_light.sample_point()).collect();
+        assert!(samples.iter().any(|p| *p != soft_light.center()));
+        // Samples stay on the light
+        for p in samples {
+            assert!(soft_light.quad.contains(p));
+        }
+    }
+
+    fn glass_sphere() -> Hittable {
+        Hittable::Sphere(Sphere {
+            radius: 1.0,
+            center: Point {
+                x: 3.,
+                y: 0.,
+                z: 0.,
+            },
+            material: Arc::new(Material {
+                material_type: MaterialType::Dielectric(Dielectric {
+                    refraction_index: 1.5,
+                    absorption: Color3::black(),
+                }),
+                albedo: Texture::Solid(Color::from([1.0, 1.0, 1.0])),
+            }),
+        })
+    }
+
+    /// Refract a ray hitting the glass sphere, never picking the Fresnel reflection
+    fn refract(hit: &HitRecord, ray: &Ray) -> Ray {
+        Ray {
+            origin: hit.p,
+            direction: ScatteredRay::dielectric_direction(hit, ray, 1.5, 1.0),
+            time: ray.time,
+        }
+    }
+
+    /// Trace a ray through the glass sphere, returns the ray leaving the sphere
+    fn refract_through(sphere: &Hittable, ray: Ray) -> Ray {
+        let interval = Interval {
+            min: 0.0001,
+            max: f64::INFINITY,
+        };
+        let entry = sphere.hit(&ray, interval).unwrap();
+        assert!(entry.front_face);
+        assert_eq!(
+            ScatteredRay::scatter(&entry, &ray).remove(0).attenuation,
+            Color3::white()
+        );
+        let inside = refract(&entry, &ray);
+        let exit = sphere.hit(&inside, interval).unwrap();
+        assert!(!exit.front_face);
+        refract(&exit, &inside)
+    }
+
+    #[test]
+    fn dielectric_straight_through() {
+        let ray = Ray {
+            origin: Point {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 1.0,
+                y: 0.,
+                z: 0.,
+            },
+            time: 0.,
+        };
+        let exit = refract_through(&glass_sphere(), ray);
+        // Hitting the surface head on, the ray isn't bent
+        assert!((exit.origin.x - 4.).abs() < 1e-9);
+        assert!((exit.direction.normalized().x - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn dielectric_bends_toward_axis() {
+        let ray = Ray {
+            origin: Point {
+                x: 0.,
+                y: 0.5,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 1.0,
+                y: 0.,
+                z: 0.,
+            },
+            time: 0.,
+        };
+        let sphere = glass_sphere();
+        let entry = sphere.hit(&ray, Interval { min: 0., max: 10. }).unwrap();
+        let inside = refract(&entry, &ray).direction.normalized();
+        // Incident angle has a sine of 0.5, Snell's law gives 0.5 / 1.5 for the refracted one
+        let cos_refracted = inside.dot(&(-entry.normal));
+        assert!(((1.0 - cos_refracted * cos_refracted).sqrt() - 0.5 / 1.5).abs() < 1e-9);
+
+        // Like a converging lens, the ray leaves the sphere bent toward the axis
+        let exit = refract_through(&sphere, ray);
+        assert!(exit.direction.y < 0.);
+        assert!(exit.direction.z.abs() < 1e-9);
+    }
+
+    #[test]
+    fn metal_fuzz_is_clamped() {
+        let material = Arc::new(Material {
+            material_type: MaterialType::Metal(Metal { fuzz: 5.0 }),
+            albedo: Texture::Solid(Color::white()),
+        });
+        let quad = Hittable::Quad(Quad {
+            material,
+            ..unit_quad()
+        });
+        let ray = ray_down_z(0.5, 0.5);
+        let hit = quad.hit(&ray, Interval { min: 0., max: 10. }).unwrap();
+        for _ in 0..1000 {
+            let scattered = ScatteredRay::scatter(&hit, &ray).remove(0);
+            assert!(scattered.ray.direction.dot(&hit.normal) >= 0.);
+            // With a fuzz of at most 1, the direction stays within 2 of the normalized reflection
+            assert!(scattered.ray.direction.len() <= 2.0 + 1e-9);
+        }
+    }
+
+    #[test]
+    fn custom_material() {
+        // Sends every ray back where it came from, and glows a little
+        #[derive(Debug)]
+        struct Retroreflector;
+
+        impl Scatter for Retroreflector {
+            fn scatter(&self, hit: &HitRecord, incident_ray: &Ray) -> Vec<ScatteredRay> {
+                vec![ScatteredRay::off_surface(
+                    hit,
+                    incident_ray,
+                    -incident_ray.direction,
+                )]
+            }
+
+            fn emitted(&self) -> Color3 {
+                Color3::white()
+            }
+        }
+
+        let custom = CustomMaterial(Arc::new(Retroreflector));
+        let material = Arc::new(Material {
+            material_type: MaterialType::Custom(custom.clone()),
+            albedo: Texture::Solid(Color::from([0.5, 0.5, 0.5])),
+        });
+        let quad = Hittable::Quad(Quad {
+            material: Arc::clone(&material),
+            ..unit_quad()
+        });
+        let ray = ray_down_z(0.5, 0.5);
+        let hit = quad.hit(&ray, Interval { min: 0., max: 10. }).unwrap();
+        let scattered = ScatteredRay::scatter(&hit, &ray).remove(0);
+        assert_eq!(scattered.ray.direction, -ray.direction);
+        assert_eq!(
+            scattered.attenuation,
+            Color3::from(Color::from([0.5, 0.5, 0.5]))
+        );
+        assert_eq!(material.emitted(), Color3::white());
+        assert!((material.average_reflectance() - 0.5).abs() < 0.01);
+        // Only the same instance is equal
+        assert_eq!(MaterialType::Custom(custom), material.material_type);
+        assert_ne!(
+            MaterialType::Custom(CustomMaterial(Arc::new(Retroreflector))),
+            material.material_type
+        );
+    }
+
+    #[test]
+    fn schlick_reflectance() {
+        // Head on, glass reflects about 4% of the light
+        assert!((reflectance(1.0, 1.5) - 0.04).abs() < 1e-9);
+        // At grazing incidence, everything is reflected
+        assert!((reflectance(0.0, 1.5) - 1.0).abs() < 1e-9);
+        assert!(reflectance(0.2, 1.5) > reflectance(0.8, 1.5));
+    }
+
+    #[test]
+    fn dielectric_reflects_on_low_sample() {
+        let ray = Ray {
+            origin: Point {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 1.0,
+                y: 0.,
+                z: 0.,
+            },
+            time: 0.,
+        };
+        let entry = glass_sphere()
+            .hit(&ray, Interval { min: 0., max: 10. })
+            .unwrap();
+        // Head on, a sample below 4% picks the reflection back toward the origin
+        let reflected = ScatteredRay::dielectric_direction(&entry, &ray, 1.5, 0.01);
+        assert!((reflected.normalized().x + 1.).abs() < 1e-9);
+        let refracted = ScatteredRay::dielectric_direction(&entry, &ray, 1.5, 0.5);
+        assert!((refracted.normalized().x - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn material_average_reflectance() {
+        let gray = Material {
+            material_type: MaterialType::Lambertian(Lambertian),
+            albedo: Texture::Solid(Color::from([0.5, 0.5, 0.5])),
+        };
+        assert!((gray.average_reflectance() - 0.5).abs() < 0.01);
+
+        let metal = Material {
+            material_type: MaterialType::Metal(Metal { fuzz: 0. }),
+            albedo: Texture::Solid(Color::from([0.0, 0.5, 1.0])),
+        };
+        assert!((metal.average_reflectance() - 0.5).abs() < 0.01);
+
+        let glass = Material {
+            material_type: MaterialType::Dielectric(Dielectric {
+                refraction_index: 1.5,
+                absorption: Color3::black(),
+            }),
+            albedo: Texture::Solid(Color::from([0.5, 0.5, 0.5])),
+        };
+        assert_eq!(glass.average_reflectance(), 1.0);
+        // Even faintly tinted glass absorbs a little
+        let tinted = Material {
+            material_type: MaterialType::Dielectric(Dielectric {
+                refraction_index: 1.5,
+                absorption: Color3 {
+                    r: 0.001,
+                    g: 0.001,
+                    b: 0.001,
+                },
+            }),
+            ..glass
+        };
+        assert!((tinted.average_reflectance() - (-0.001_f64).exp()).abs() < 1e-12);
+
+        let light = Material {
+            material_type: MaterialType::DiffuseLight(DiffuseLight {
+                emission: Color3::white(),
+            }),
+            albedo: Texture::Solid(Color::white()),
+        };
+        assert_eq!(light.average_reflectance(), 0.0);
+    }
+
+    #[test]
+    fn material_from_seed_is_deterministic() {
+        for (x, z) in [(0, 0), (-3, 7), (11, -2)] {
+            assert_eq!(material_from_seed(x, z, 42), material_from_seed(x, z, 42));
+        }
+        let material = material_from_seed(0, 0, 42);
+        assert_ne!(material, material_from_seed(1, 0, 42));
+        assert_ne!(material, material_from_seed(0, 1, 42));
+        assert_ne!(material, material_from_seed(0, 0, 43));
+    }
+
+    #[test]
+    fn sphere_bounding_box() {
+        let sphere = Hittable::Sphere(Sphere {
+            center: Point {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            radius: 1.,
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        });
+        let unit = Interval { min: -1., max: 1. };
+        assert_eq!(
+            sphere.bounding_box(),
+            Aabb {
+                x: unit,
+                y: unit,
+                z: unit
+            }
+        );
+    }
+
+    #[test]
+    fn plane_bounding_box() {
+        let plane = Hittable::Plane(Plane {
+            point: Vec3::ZERO,
+            normal: Vec3::new(0., 1., 0.),
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        });
+        assert_eq!(
+            plane.bounding_box(),
+            Aabb {
+                x: Interval::UNIVERSE,
+                y: Interval::UNIVERSE,
+                z: Interval::UNIVERSE
+            }
+        );
+    }
+
+    #[test]
+    fn hit_named_sphere() {
+        let sphere = |z: f64| Sphere {
+            center: Point { x: 3., y: 0., z },
+            radius: 1.,
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        };
+        let name: Arc<str> = Arc::from("target");
+        let world = World {
+            objects: vec![
+                Arc::new(Hittable::Named(Named {
+                    object: Arc::new(Hittable::Sphere(sphere(0.))),
+                    name: Arc::clone(&name),
+                })),
+                Arc::new(Hittable::Sphere(sphere(5.))),
+            ],
+            lights: vec![],
+        };
+        let ray_to = |z: f64| Ray {
+            origin: Point {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            direction: Vec3 { x: 3., y: 0., z },
+            time: 0.,
+        };
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        let hit = world.hit(&ray_to(0.), interval).unwrap();
+        assert_eq!(hit.name.as_deref(), Some("target"));
+        // The record shares the name of the object
+        assert!(Arc::ptr_eq(&hit.name.unwrap(), &name));
+        let hit = world.hit(&ray_to(5.), interval).unwrap();
+        assert_eq!(hit.name, None);
+    }
+
+    #[test]
+    fn bvh_matches_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let objects: Vec<Arc<Hittable>> = (0..200)
+            .map(|i| {
+                Arc::new(Hittable::Sphere(Sphere {
+                    center: Point {
+                        x: rng.gen_range(-10.0..10.0),
+                        y: rng.gen_range(-10.0..10.0),
+                        z: rng.gen_range(-10.0..10.0),
+                    },
+                    radius: rng.gen_range(0.1..1.0),
+                    material: Arc::new(material_from_seed(i, 0, 7)),
+                }))
+            })
+            .collect();
+        let linear = World {
+            objects: objects.clone(),
+            lights: vec![],
+        };
+        let bvh = World {
+            objects,
+            lights: vec![],
+        }
+        .build_bvh();
+        assert_eq!(bvh.objects.len(), 1);
+
+        let interval = Interval {
+            min: 0.001,
+            max: f64::INFINITY,
+        };
+        let mut hits = 0;
+        for _ in 0..500 {
+            let ray = Ray {
+                origin: Point {
+                    x: rng.gen_range(-15.0..15.0),
+                    y: rng.gen_range(-15.0..15.0),
+                    z: -20.,
+                },
+                direction: Vec3 {
+                    x: rng.gen_range(-0.5..0.5),
+                    y: rng.gen_range(-0.5..0.5),
+                    z: 1.,
+                },
+                time: 0.,
+            };
+            let expected = linear.hit(&ray, interval);
+            hits += expected.is_some() as u32;
+            assert_eq!(bvh.hit(&ray, interval), expected);
+        }
+        // Make sure the comparison isn't only made on misses
+        assert!(hits > 50);
+    }
+
+    #[test]
+    fn mesh_bvh_matches_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let material = Arc::new(material_from_seed(0, 0, 11));
+        // Bumpy height field over a 40x40 grid, two triangles per cell
+        let size = 40;
+        let heights: Vec<Vec<f64>> = (0..=size)
+            .map(|_| (0..=size).map(|_| rng.gen_range(-0.5..0.5)).collect())
+            .collect();
+        let vertex = |i: usize, j: usize| Point {
+            x: i as f64,
+            y: heights[i][j],
+            z: j as f64,
+        };
+        let mut triangles = vec![];
+        for i in 0..size {
+            for j in 0..size {
+                for (a, b, c) in [
+                    (vertex(i, j), vertex(i + 1, j), vertex(i + 1, j + 1)),
+                    (vertex(i, j), vertex(i + 1, j + 1), vertex(i, j + 1)),
+                ] {
+                    triangles.push(Triangle {
+                        a,
+                        b,
+                        c,
+                        normals: None,
+                        material: Arc::clone(&material),
+                        cull_backfaces: false,
+                    });
+                }
+            }
+        }
+        let linear = World {
+            objects: triangles
+                .iter()
+                .map(|triangle| {
+                    Arc::new(Hittable::Triangle(Triangle {
+                        normals: None,
+                        material: Arc::clone(&material),
+                        ..*triangle
+                    }))
+                })
+                .collect(),
+            lights: vec![],
+        };
+        let mesh = Hittable::Mesh(Mesh::new(triangles, false));
+        let bbox = mesh.bounding_box();
+        assert_eq!((bbox.x.min, bbox.x.max), (0., size as f64));
+        assert_eq!((bbox.z.min, bbox.z.max), (0., size as f64));
+
+        let interval = Interval {
+            min: 0.001,
+            max: f64::INFINITY,
+        };
+        let mut hits = 0;
+        for _ in 0..500 {
+            let ray = Ray {
+                origin: Point {
+                    x: rng.gen_range(-5.0..45.0),
+                    y: 10.,
+                    z: rng.gen_range(-5.0..45.0),
                 },
-                radius: 0.5,
-                material: Rc::clone(&material_left),
-            })),
-            Rc::new(Hittable::Sphere(Sphere {
-                center: Point {
-                    x: 1.2,
-                    y: 0.,
-                    z: 1.,
+                direction: Vec3 {
+                    x: rng.gen_range(-0.5..0.5),
+                    y: -1.,
+                    z: rng.gen_range(-0.5..0.5),
                 },
-                radius: 0.5,
-                material: Rc::clone(&material_right),
-            })),
-        ]
+                time: 0.,
+            };
+            let expected = linear.hit(&ray, interval);
+            hits += expected.is_some() as u32;
+            assert_eq!(mesh.hit(&ray, interval), expected);
+        }
+        assert!(hits > 50);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn ray_along_x(y: f64, z: f64) -> Ray {
+        Ray {
+            origin: Point { x: -5., y, z },
+            direction: Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            time: 0.,
+        }
+    }
 
     #[test]
-    fn vec3_normalized() {
-        let v = Vec3 {
-            x: 1.0,
-            y: 1.0,
-            z: 1.0,
+    fn aabb_from_swapped_points() {
+        let a = Point {
+            x: 1.,
+            y: -1.,
+            z: 2.,
+        };
+        let b = Point {
+            x: -1.,
+            y: 1.,
+            z: 0.,
+        };
+        let aabb = Aabb::from_points(a, b);
+        assert_eq!(aabb, Aabb::from_points(b, a));
+        assert_eq!(aabb.x, Interval { min: -1., max: 1. });
+        assert_eq!(aabb.y, Interval { min: -1., max: 1. });
+        assert_eq!(aabb.z, Interval { min: 0., max: 2. });
+    }
+
+    #[test]
+    fn aabb_merge() {
+        let a = Aabb::from_points(
+            Point {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            Point {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+        );
+        let b = Aabb::from_points(
+            Point {
+                x: 2.,
+                y: -1.,
+                z: 0.5,
+            },
+            Point {
+                x: 3.,
+                y: 0.5,
+                z: 0.7,
+            },
+        );
+        let merged = Aabb::merge(&a, &b);
+        assert_eq!(merged.x, Interval { min: 0., max: 3. });
+        assert_eq!(merged.y, Interval { min: -1., max: 1. });
+        assert_eq!(merged.z, Interval { min: 0., max: 1. });
+    }
+
+    #[test]
+    fn aabb_hit() {
+        let aabb = Aabb::from_points(
+            Point {
+                x: -1.,
+                y: -1.,
+                z: -1.,
+            },
+            Point {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            },
+        );
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        assert!(aabb.hit(&ray_along_x(0.5, -0.5), interval));
+        assert!(!aabb.hit(&ray_along_x(1.5, 0.), interval));
+        // The box is behind the end of the interval
+        assert!(!aabb.hit(&ray_along_x(0., 0.), Interval { min: 0., max: 2. }));
+        let diagonal = Ray {
+            origin: Point {
+                x: -5.,
+                y: -5.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 1.,
+                y: 1.2,
+                z: 0.,
+            },
+            time: 0.,
+        };
+        assert!(aabb.hit(&diagonal, interval));
+    }
+
+    #[test]
+    fn aabb_hit_flat_box() {
+        // No width along x, like the box of a quad facing the ray
+        let flat = Aabb::from_points(
+            Point {
+                x: 0.,
+                y: -1.,
+                z: -1.,
+            },
+            Point {
+                x: 0.,
+                y: 1.,
+                z: 1.,
+            },
+        );
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        assert!(flat.hit(&ray_along_x(0., 0.), interval));
+        assert!(!flat.hit(&ray_along_x(2., 0.), interval));
+    }
+
+    #[test]
+    fn hit_plane() {
+        let plane = Hittable::Plane(Plane {
+            point: Point {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            normal: Vec3 {
+                x: 0.,
+                y: 2.,
+                z: 0.,
+            },
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        });
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        let down = Ray {
+            origin: Point {
+                x: 0.,
+                y: 5.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 1.,
+                y: -1.,
+                z: 0.,
+            },
+            time: 0.,
         };
+        let hit = plane.hit(&down, interval).unwrap();
+        assert_eq!(hit.t, 5.);
         assert_eq!(
-            v.normalized(),
+            hit.p,
+            Point {
+                x: 5.,
+                y: 0.,
+                z: 0.
+            }
+        );
+        assert!(hit.front_face);
+        assert_eq!(
+            hit.normal,
             Vec3 {
-                x: 1.0 / 3.0_f64.sqrt(),
-                y: 1.0 / 3.0_f64.sqrt(),
-                z: 1.0 / 3.0_f64.sqrt(),
+                x: 0.,
+                y: 1.,
+                z: 0.
+            }
+        );
+
+        // From below, the normal is flipped toward the ray
+        let up = Ray {
+            origin: Point {
+                x: 0.,
+                y: -1.,
+                z: 0.,
+            },
+            direction: Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            time: 0.,
+        };
+        let hit = plane.hit(&up, interval).unwrap();
+        assert!(!hit.front_face);
+        assert_eq!(hit.normal.y, -1.);
+
+        // Parallel and receding rays miss it
+        let parallel = Ray {
+            direction: Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            ..down
+        };
+        assert_eq!(plane.hit(&parallel, interval), None);
+        let receding = Ray {
+            direction: Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            ..down
+        };
+        assert_eq!(plane.hit(&receding, interval), None);
+    }
+
+    fn unit_quad() -> Quad {
+        // Unit square of the xy plane, facing +z
+        Quad {
+            q: Point {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            u: Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            v: Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        }
+    }
+
+    fn ray_down_z(x: f64, y: f64) -> Ray {
+        Ray {
+            origin: Point { x, y, z: 2. },
+            direction: Vec3 {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            time: 0.,
+        }
+    }
+
+    #[test]
+    fn hit_quad() {
+        let quad = Hittable::Quad(unit_quad());
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        let hit = quad.hit(&ray_down_z(0.5, 0.5), interval).unwrap();
+        assert_eq!(hit.t, 2.);
+        assert_eq!(
+            hit.p,
+            Point {
+                x: 0.5,
+                y: 0.5,
+                z: 0.
+            }
+        );
+        assert!(hit.front_face);
+        assert_eq!(
+            hit.normal,
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.
             }
         );
+
+        // Just inside and just outside of the edges
+        assert!(quad.hit(&ray_down_z(0.999, 0.001), interval).is_some());
+        assert_eq!(quad.hit(&ray_down_z(1.001, 0.5), interval), None);
+        assert_eq!(quad.hit(&ray_down_z(0.5, -0.001), interval), None);
     }
 
     #[test]
-    fn vec3_len() {
-        let v = Vec3 {
-            x: 1.0,
-            y: 1.0,
-            z: 1.0,
+    fn quad_planar_coordinates() {
+        let quad = Quad {
+            u: Vec3 {
+                x: 2.,
+                y: 0.,
+                z: 0.,
+            },
+            ..unit_quad()
         };
-        assert_eq!(v.len(), 3.0_f64.sqrt())
+        let (alpha, beta) = quad.planar_coordinates(Point {
+            x: 0.5,
+            y: 0.25,
+            z: 0.,
+        });
+        assert!((alpha - 0.25).abs() < 1e-12);
+        assert!((beta - 0.25).abs() < 1e-12);
     }
 
     #[test]
-    fn hit_sphere() {
-        let material_test = Rc::new(Material {
-            material_type: MaterialType::Lambertian,
-            albedo: Color::from([0.9, 0.9, 0.9]),
+    fn triangle_backface_culling() {
+        let triangle = Triangle {
+            a: Point::new(0., 0., 0.),
+            b: Point::new(1., 0., 0.),
+            c: Point::new(0., 1., 0.),
+            normals: None,
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+            cull_backfaces: false,
+        };
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        let from_front = ray_down_z(0.25, 0.25);
+        let from_back = Ray {
+            origin: Point::new(0.25, 0.25, -2.),
+            direction: Vec3::new(0., 0., 1.),
+            time: 0.,
+        };
+        let two_sided = Hittable::Triangle(triangle.clone());
+        assert!(two_sided.hit(&from_front, interval).unwrap().front_face);
+        assert!(!two_sided.hit(&from_back, interval).unwrap().front_face);
+
+        let culled = Hittable::Triangle(Triangle {
+            cull_backfaces: true,
+            ..triangle.clone()
         });
-        let sphere = Sphere {
-            radius: 1.0,
-            center: Point {
-                x: 3.,
+        assert!(culled.hit(&from_front, interval).unwrap().front_face);
+        assert_eq!(culled.hit(&from_back, interval), None);
+
+        // The mesh setting applies to all of its triangles
+        let mesh = Hittable::Mesh(Mesh::new(vec![triangle.clone()], true));
+        assert!(mesh.hit(&from_front, interval).is_some());
+        assert_eq!(mesh.hit(&from_back, interval), None);
+        let mesh = Hittable::Mesh(Mesh::new(
+            vec![Triangle {
+                cull_backfaces: true,
+                ..triangle
+            }],
+            false,
+        ));
+        assert!(mesh.hit(&from_back, interval).is_some());
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let mesh = Hittable::Mesh(Mesh::new(vec![], false));
+        assert_eq!(mesh.bounding_box(), Aabb::EMPTY);
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        assert_eq!(mesh.hit(&ray_down_z(0.25, 0.25), interval), None);
+        // The empty box doesn't change the box of the objects around it
+        let bvh = BvhNode::new(vec![Arc::new(mesh), Arc::new(glass_sphere())]);
+        assert_eq!(bvh.bbox, glass_sphere().bounding_box());
+    }
+
+    #[test]
+    fn hit_triangle() {
+        let triangle = Hittable::Triangle(Triangle {
+            a: Point {
+                x: 0.,
                 y: 0.,
                 z: 0.,
             },
-            material: Rc::clone(&material_test),
+            b: Point {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            c: Point {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            normals: None,
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+            cull_backfaces: false,
+        });
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
         };
-        let ray_should_hit = Ray {
-            origin: Point {
+        let hit = triangle.hit(&ray_down_z(0.25, 0.25), interval).unwrap();
+        assert_eq!(hit.t, 2.);
+        assert_eq!(
+            hit.p,
+            Point {
+                x: 0.25,
+                y: 0.25,
+                z: 0.
+            }
+        );
+        assert!(hit.front_face);
+        assert_eq!(
+            hit.normal,
+            Vec3 {
                 x: 0.,
                 y: 0.,
+                z: 1.
+            }
+        );
+        // Outside of the hypotenuse
+        assert_eq!(triangle.hit(&ray_down_z(0.6, 0.6), interval), None);
+        // Out of the interval
+        assert_eq!(
+            triangle.hit(&ray_down_z(0.25, 0.25), Interval { min: 0., max: 1. }),
+            None
+        );
+        // Parallel to the triangle
+        let parallel = Ray {
+            origin: Point {
+                x: -1.,
+                y: 0.25,
                 z: 0.,
             },
             direction: Vec3 {
-                x: 1.0,
+                x: 1.,
                 y: 0.,
                 z: 0.,
             },
+            time: 0.,
         };
-        assert_eq!(
-            Hittable::hit(
-                &Hittable::Sphere(sphere),
-                &ray_should_hit,
-                Interval { min: 0., max: 100. }
-            ),
-            Some(HitRecord {
-                p: Vec3 {
-                    x: 2.,
-                    y: 0.,
+        assert_eq!(triangle.hit(&parallel, interval), None);
+    }
+
+    #[test]
+    fn scale_into_ellipsoid() {
+        let unit_sphere = Arc::new(Hittable::Sphere(Sphere {
+            center: Point {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            },
+            radius: 1.,
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+        }));
+        // x^2 / 4 + y^2 + z^2 = 1
+        let ellipsoid = Hittable::Scale(Scale {
+            object: unit_sphere,
+            factor: Vec3 {
+                x: 2.,
+                y: 1.,
+                z: 1.,
+            },
+        });
+        let bbox = ellipsoid.bounding_box();
+        assert_eq!(bbox.x, Interval { min: -2., max: 2. });
+        assert_eq!(bbox.y, Interval { min: -1., max: 1. });
+
+        let ray = Ray {
+            origin: Point {
+                x: -5.,
+                y: 0.5,
+                z: 0.,
+            },
+            ..ray_along_x(0., 0.)
+        };
+        let hit = ellipsoid
+            .hit(&ray, Interval { min: 0., max: 100. })
+            .unwrap();
+        let expected_x = -(3.0_f64.sqrt());
+        assert!(
+            (hit.p
+                - Point {
+                    x: expected_x,
+                    y: 0.5,
                     z: 0.
-                },
-                normal: Vec3 {
-                    x: -1.,
+                })
+            .len()
+                < 1e-9
+        );
+        assert!(hit.front_face);
+        // Gradient of the ellipsoid equation, not the one of the unit sphere stretched
+        let gradient = Vec3 {
+            x: expected_x / 4.,
+            y: 0.5,
+            z: 0.,
+        }
+        .normalized();
+        assert!((hit.normal - gradient).len() < 1e-9);
+    }
+
+    #[test]
+    fn triangle_smooth_shading() {
+        // Face of an octahedron approximating the unit sphere, the vertex normals are the ones
+        // of the sphere
+        let vertices = [
+            Point {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            Point {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            Point {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+        ];
+        let triangle = Hittable::Triangle(Triangle {
+            a: vertices[0],
+            b: vertices[1],
+            c: vertices[2],
+            normals: Some(vertices),
+            material: Arc::new(Material {
+                material_type: MaterialType::Lambertian(Lambertian),
+                albedo: Texture::Solid(Color::white()),
+            }),
+            cull_backfaces: false,
+        });
+        let interval = Interval {
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        // Rays from the center of the sphere, the smooth normal follows their direction
+        let normal_toward = |direction: Vec3| {
+            let ray = Ray {
+                origin: Point {
+                    x: 0.,
                     y: 0.,
-                    z: 0.
+                    z: 0.,
                 },
-                t: 2.,
-                front_face: true,
-                material: Rc::clone(&material_test),
-            })
-        )
+                direction,
+                time: 0.,
+            };
+            let hit = triangle.hit(&ray, interval).unwrap();
+            // Hit from inside the mesh
+            assert!(!hit.front_face);
+            -hit.normal
+        };
+        let center = normal_toward(Vec3 {
+            x: 1.,
+            y: 1.,
+            z: 1.,
+        });
+        assert!(
+            (center
+                - (1. / 3.0_f64.sqrt())
+                    * Vec3 {
+                        x: 1.,
+                        y: 1.,
+                        z: 1.
+                    })
+            .len()
+                < 1e-9
+        );
+        let near_vertex = normal_toward(Vec3 {
+            x: 1.,
+            y: 0.01,
+            z: 0.01,
+        });
+        assert!((near_vertex - vertices[0]).len() < 0.03);
+        // Nearby hits get nearby normals
+        let mut previous = near_vertex;
+        for step in 1..=50 {
+            let y = 0.01 + step as f64 * 0.02;
+            let normal = normal_toward(Vec3 { x: 1., y, z: 0.01 });
+            assert!((normal.len() - 1.).abs() < 1e-9);
+            assert!((normal - previous).len() < 0.05);
+            previous = normal;
+        }
+    }
+
+    #[test]
+    fn diffuse_light_emits_without_scattering() {
+        let emission = Color3 {
+            r: 4.,
+            g: 3.6,
+            b: 3.2,
+        };
+        let light = Hittable::Quad(Quad {
+            material: Arc::new(Material {
+                material_type: MaterialType::DiffuseLight(DiffuseLight { emission }),
+                albedo: Texture::Solid(Color::white()),
+            }),
+            ..unit_quad()
+        });
+        let ray = ray_down_z(0.5, 0.5);
+        let hit = light
+            .hit(
+                &ray,
+                Interval {
+                    min: 0.,
+                    max: f64::INFINITY,
+                },
+            )
+            .unwrap();
+        assert_eq!(hit.material.emitted(), emission);
+        assert!(ScatteredRay::scatter(&hit, &ray).is_empty());
+
+        let diffuse = Material {
+            material_type: MaterialType::Lambertian(Lambertian),
+            albedo: Texture::Solid(Color::white()),
+        };
+        assert_eq!(diffuse.emitted(), Color3::black());
     }
 }
@@ -0,0 +1,319 @@
+//! Scene descriptions stored as JSON files, so that scenes can be changed without recompiling.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::image::Camera;
+use crate::object::{AreaLight, Hittable, Point, Vec3, World};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    /// The file isn't valid JSON, or doesn't describe a scene
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(error) => write!(f, "couldn't read the scene: {error}"),
+            SceneError::Json(error) => write!(f, "invalid scene: {error}"),
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+    fn from(error: io::Error) -> Self {
+        SceneError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for SceneError {
+    fn from(error: serde_json::Error) -> Self {
+        SceneError::Json(error)
+    }
+}
+
+/// Parameters of `Camera::look_at`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraSettings {
+    pub aspect_ratio: f64,
+    pub image_width: u32,
+    pub sample_per_pixel: u32,
+    pub max_ray_bounces: u16,
+    pub vertical_fov: f64,
+    pub defocus_angle: f64,
+    /// Distance to the plane in focus, the one of lookat when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_dist: Option<f64>,
+    pub lookfrom: Point,
+    pub lookat: Point,
+    pub vup: Vec3,
+    /// Sample the lights of the scene directly, see `Camera::with_next_event_estimation`
+    #[serde(default)]
+    pub next_event_estimation: bool,
+}
+
+impl CameraSettings {
+    pub fn camera(&self) -> Camera {
+        Camera::look_at(
+            self.aspect_ratio,
+            self.image_width,
+            self.sample_per_pixel,
+            self.max_ray_bounces,
+            self.vertical_fov,
+            self.defocus_angle,
+            self.focus_dist
+                .unwrap_or_else(|| (self.lookfrom - self.lookat).len()),
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+        )
+        .with_next_event_estimation(self.next_event_estimation)
+    }
+}
+
+/// Camera and objects of a scene
+#[derive(Serialize, Deserialize)]
+pub struct Scene {
+    pub camera: CameraSettings,
+    pub objects: Vec<Arc<Hittable>>,
+    /// Lights sampled directly, added to the world both as objects and as lights, see
+    /// `World::add_light`. They aren't repeated among the objects.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lights: Vec<AreaLight>,
+}
+
+impl Scene {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+        Scene::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_json(text: &str) -> Result<Scene, SceneError> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, SceneError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SceneError> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// World made of the objects and the lights of the scene
+    pub fn world(&self) -> World {
+        let mut world = World {
+            objects: self.objects.clone(),
+            lights: vec![],
+        };
+        for light in &self.lights {
+            world.add_light(light.clone());
+        }
+        world
+    }
+}
+
+impl World {
+    /// World described by the scene file at `path`
+    pub fn from_file(path: impl AsRef<Path>) -> Result<World, SceneError> {
+        Ok(Scene::from_file(path)?.world())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{Color, Color3};
+    use crate::object::{
+        AnisotropicMetal, Dielectric, DiffuseLight, Isotropic, Lambertian, Material, MaterialType,
+        Metal, Quad, Sphere,
+    };
+    use crate::texture::{Checker, NoiseTexture, Texture};
+
+    #[test]
+    fn material_round_trip() {
+        let materials = [
+            MaterialType::Lambertian(Lambertian),
+            MaterialType::Metal(Metal { fuzz: 0.1 }),
+            MaterialType::AnisotropicMetal(AnisotropicMetal {
+                fuzz_u: 0.5,
+                fuzz_v: 0.05,
+            }),
+            MaterialType::Dielectric(Dielectric {
+                refraction_index: 1.5,
+                absorption: Color3::black(),
+            }),
+            MaterialType::Dielectric(Dielectric {
+                refraction_index: 1.3,
+                absorption: Color3 {
+                    r: 0.5,
+                    g: 0.1,
+                    b: 0.,
+                },
+            }),
+            MaterialType::DiffuseLight(DiffuseLight {
+                emission: Color3 {
+                    r: 15.,
+                    g: 12.,
+                    b: 0.5,
+                },
+            }),
+            MaterialType::Isotropic(Isotropic),
+        ];
+        let textures = [
+            Texture::Solid(Color {
+                r: 25,
+                g: 51,
+                b: 127,
+            }),
+            Texture::Checker(Checker {
+                scale: 0.5,
+                even: Color::white(),
+                odd: Color::black(),
+            }),
+            Texture::Noise(NoiseTexture::new(4., 7)),
+        ];
+        for material_type in materials {
+            for albedo in textures.clone() {
+                let material = Material {
+                    material_type: material_type.clone(),
+                    albedo,
+                };
+                let text = serde_json::to_string(&material).unwrap();
+                let parsed: Material = serde_json::from_str(&text).unwrap();
+                assert_eq!(parsed, material);
+            }
+        }
+        // Clear glass and solid textures keep the short forms
+        let glass: Material =
+            serde_json::from_str(r#"{"type": "dielectric", "refraction_index": 1.5}"#).unwrap();
+        assert_eq!(
+            glass.material_type,
+            MaterialType::Dielectric(Dielectric {
+                refraction_index: 1.5,
+                absorption: Color3::black(),
+            })
+        );
+        assert_eq!(glass.albedo, Texture::Solid(Color::white()));
+    }
+
+    #[test]
+    fn scene_round_trip() {
+        let material = Arc::new(Material {
+            material_type: MaterialType::Metal(Metal { fuzz: 0.3 }),
+            albedo: Texture::Solid(Color::white()),
+        });
+        let scene = Scene {
+            camera: CameraSettings {
+                aspect_ratio: 1.5,
+                image_width: 500,
+                sample_per_pixel: 100,
+                max_ray_bounces: 50,
+                vertical_fov: 90.,
+                defocus_angle: 2.,
+                focus_dist: None,
+                lookfrom: Point::new(-1.5, 1.5, 0.),
+                lookat: Point::new(1.2, 0., 0.),
+                vup: Vec3::new(0., 1., 0.),
+                next_event_estimation: false,
+            },
+            objects: vec![
+                Arc::new(Hittable::Sphere(Sphere {
+                    center: Point::new(0.1, -100.5, 1. / 3.),
+                    radius: 100.,
+                    material: Arc::clone(&material),
+                })),
+                Arc::new(Hittable::Quad(Quad {
+                    q: Point::new(0., 1., 0.),
+                    u: Vec3::new(1., 0., 0.),
+                    v: Vec3::new(0., 0., 1.),
+                    material,
+                })),
+            ],
+            lights: vec![],
+        };
+        let text = scene.to_json().unwrap();
+        let parsed = Scene::from_json(&text).unwrap();
+        assert_eq!(parsed.camera, scene.camera);
+        assert_eq!(parsed.objects.len(), 2);
+        match (&*parsed.objects[0], &*scene.objects[0]) {
+            (Hittable::Sphere(parsed), Hittable::Sphere(sphere)) => {
+                assert_eq!(parsed.center, sphere.center);
+                assert_eq!(parsed.radius, sphere.radius);
+                assert_eq!(parsed.material, sphere.material);
+            }
+            _ => panic!("the first object should be a sphere"),
+        }
+        assert!(matches!(*parsed.objects[1], Hittable::Quad(_)));
+        assert_eq!(parsed.to_json().unwrap(), text);
+    }
+
+    #[test]
+    fn scene_with_lights() {
+        let text = r#"{
+            "camera": {
+                "aspect_ratio": 1, "image_width": 100, "sample_per_pixel": 10,
+                "max_ray_bounces": 5, "vertical_fov": 40, "defocus_angle": 0,
+                "lookfrom": [0, 1, -3], "lookat": [0, 1, 0], "vup": [0, 1, 0],
+                "next_event_estimation": true
+            },
+            "objects": [
+                {"type": "sphere", "center": [0, 1, 0], "radius": 1,
+                 "material": {"type": "lambertian"}}
+            ],
+            "lights": [
+                {"q": [-0.5, 3, -0.5], "u": [1, 0, 0], "v": [0, 0, 1],
+                 "material": {"type": "diffuse_light", "emission": [4, 4, 4]}}
+            ]
+        }"#;
+        let scene = Scene::from_json(text).unwrap();
+        assert!(scene.camera.next_event_estimation);
+        assert_eq!(scene.lights[0].shadow_softness, 1.);
+        let world = scene.world();
+        // The light is also an object, to be hit by the scattered rays
+        assert_eq!(world.objects.len(), 2);
+        assert_eq!(world.lights.len(), 1);
+        assert!(world.lights_contain(Point::new(0., 3., 0.)));
+
+        let parsed = Scene::from_json(&scene.to_json().unwrap()).unwrap();
+        assert!(parsed.camera.next_event_estimation);
+        assert_eq!(parsed.lights.len(), 1);
+        assert_eq!(
+            parsed.lights[0].quad.material,
+            scene.lights[0].quad.material
+        );
+    }
+
+    #[test]
+    fn invalid_scenes() {
+        assert!(matches!(Scene::from_json("{}"), Err(SceneError::Json(_))));
+        assert!(Scene::from_json("[1, 2").is_err());
+        let color = |text: &str| serde_json::from_str::<Color>(text);
+        assert!(color("[0, 0, 256]").is_err());
+        assert!(color("[0, 0.5, 0]").is_err());
+        assert!(color("[0, 0]").is_err());
+        assert!(serde_json::from_str::<Material>(r#"{"type": "plastic"}"#).is_err());
+    }
+
+    #[test]
+    fn three_spheres_scene_file() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/three_spheres.json");
+        let scene = Scene::from_file(&path).unwrap();
+        assert_eq!(scene.objects.len(), 4);
+        assert_eq!(World::from_file(&path).unwrap().objects.len(), 4);
+        assert!(matches!(
+            Scene::from_file("missing.json"),
+            Err(SceneError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,296 @@
+//! Built-in demo scenes, each with a camera framing it. See `scene` to load scenes from files.
+
+use std::sync::Arc;
+
+use crate::image::{Background, Camera, Color, Color3};
+use crate::object::{
+    AnisotropicMetal, AreaLight, BoxPrim, Dielectric, DiffuseLight, Hittable, Lambertian, Material,
+    MaterialType, Metal, Point, Quad, RotateY, Sphere, Translate, Vec3, World,
+};
+use crate::texture::{Checker, Texture};
+
+fn material(material_type: MaterialType, albedo: [f64; 3]) -> Arc<Material> {
+    Arc::new(Material {
+        material_type,
+        albedo: Texture::Solid(Color::from(albedo)),
+    })
+}
+
+fn sphere(x: f64, y: f64, z: f64, radius: f64, material: Arc<Material>) -> Hittable {
+    Hittable::Sphere(Sphere {
+        center: Point::new(x, y, z),
+        radius,
+        material,
+    })
+}
+
+/// Three spheres side by side on a ground: diffuse, fuzzy metal and shiny metal
+pub fn three_close_spheres() -> (World, Camera) {
+    let mut world = World::new();
+    world
+        .add(sphere(
+            0.,
+            -100.5,
+            0.,
+            100.,
+            material(MaterialType::Lambertian(Lambertian), [0.5, 0.5, 0.5]),
+        ))
+        .add(sphere(
+            1.2,
+            0.,
+            0.,
+            0.5,
+            material(MaterialType::Lambertian(Lambertian), [0.1, 0.2, 0.5]),
+        ))
+        .add(sphere(
+            1.2,
+            0.,
+            -1.,
+            0.5,
+            material(MaterialType::Metal(Metal { fuzz: 1.0 }), [0.8, 0.8, 0.8]),
+        ))
+        .add(sphere(
+            1.2,
+            0.,
+            1.,
+            0.5,
+            material(MaterialType::Metal(Metal { fuzz: 0.1 }), [0.8, 0.6, 0.2]),
+        ));
+
+    // Angled overhead view of the spheres, focused on the sphere the camera looks at
+    let camera = Camera::builder()
+        .aspect_ratio(3.0 / 2.0)
+        .image_width(500)
+        .defocus_angle(2.0)
+        .lookfrom(Point::new(-1.5, 1.5, 0.))
+        .lookat(Point::new(1.2, 0., 0.))
+        .build();
+    (world, camera)
+}
+
+/// Box of 555 units with a red and a green side wall, lit by a ceiling light, holding two
+/// rotated white blocks
+pub fn cornell_box() -> (World, Camera) {
+    let red = material(MaterialType::Lambertian(Lambertian), [0.65, 0.05, 0.05]);
+    let white = material(MaterialType::Lambertian(Lambertian), [0.73, 0.73, 0.73]);
+    let green = material(MaterialType::Lambertian(Lambertian), [0.12, 0.45, 0.15]);
+    let light = Arc::new(Material {
+        material_type: MaterialType::DiffuseLight(DiffuseLight {
+            emission: Color3 {
+                r: 15.,
+                g: 15.,
+                b: 15.,
+            },
+        }),
+        albedo: Texture::Solid(Color::black()),
+    });
+    let quad = |q: Point, u: Vec3, v: Vec3, material: &Arc<Material>| {
+        Hittable::Quad(Quad {
+            q,
+            u,
+            v,
+            material: Arc::clone(material),
+        })
+    };
+    // Block standing on the floor, turned by `angle` degrees and moved to (x, z)
+    let block = |size: Point, angle: f64, x: f64, z: f64| {
+        let block = BoxPrim::new(Point::ZERO, size, Arc::clone(&white));
+        Hittable::Translate(Translate {
+            object: Arc::new(Hittable::RotateY(RotateY::new(
+                Arc::new(Hittable::Box(block)),
+                angle,
+            ))),
+            offset: Point::new(x, 0., z),
+        })
+    };
+
+    let mut world = World::new();
+    world
+        .add(quad(
+            Point::new(555., 0., 0.),
+            Vec3::new(0., 555., 0.),
+            Vec3::new(0., 0., 555.),
+            &green,
+        ))
+        .add(quad(
+            Point::ZERO,
+            Vec3::new(0., 555., 0.),
+            Vec3::new(0., 0., 555.),
+            &red,
+        ))
+        .add_light(AreaLight {
+            quad: Quad {
+                q: Point::new(343., 554., 332.),
+                u: Vec3::new(-130., 0., 0.),
+                v: Vec3::new(0., 0., -105.),
+                material: light,
+            },
+            shadow_softness: 1.,
+        })
+        .add(quad(
+            Point::ZERO,
+            Vec3::new(555., 0., 0.),
+            Vec3::new(0., 0., 555.),
+            &white,
+        ))
+        .add(quad(
+            Point::new(555., 555., 555.),
+            Vec3::new(-555., 0., 0.),
+            Vec3::new(0., 0., -555.),
+            &white,
+        ))
+        .add(quad(
+            Point::new(0., 0., 555.),
+            Vec3::new(555., 0., 0.),
+            Vec3::new(0., 555., 0.),
+            &white,
+        ))
+        .add(block(Point::new(165., 330., 165.), 15., 265., 295.))
+        .add(block(Point::new(165., 165., 165.), -18., 130., 65.));
+
+    let camera = Camera::builder()
+        .aspect_ratio(1.0)
+        .image_width(600)
+        .sample_per_pixel(200)
+        .vfov_degrees(40.)
+        .lookfrom(Point::new(278., 278., -800.))
+        .lookat(Point::new(278., 278., 0.))
+        .build()
+        .with_background(Background::Solid(Color::black()))
+        .with_next_event_estimation(true);
+    (world, camera)
+}
+
+/// Row of metal spheres from mirror to brushed and rough
+pub fn metal_showcase() -> (World, Camera) {
+    let metal = [0.8, 0.8, 0.85];
+    let mut world = World::new();
+    world
+        .add(sphere(
+            0.,
+            -100.5,
+            0.,
+            100.,
+            material(MaterialType::Lambertian(Lambertian), [0.4, 0.4, 0.4]),
+        ))
+        .add(sphere(
+            3.,
+            0.,
+            -2.2,
+            0.5,
+            material(MaterialType::Metal(Metal { fuzz: 0. }), metal),
+        ))
+        .add(sphere(
+            3.,
+            0.,
+            -1.1,
+            0.5,
+            material(MaterialType::Metal(Metal { fuzz: 0.2 }), [0.9, 0.6, 0.3]),
+        ))
+        .add(sphere(
+            3.,
+            0.,
+            0.,
+            0.5,
+            material(
+                MaterialType::AnisotropicMetal(AnisotropicMetal {
+                    fuzz_u: 0.6,
+                    fuzz_v: 0.05,
+                }),
+                metal,
+            ),
+        ))
+        .add(sphere(
+            3.,
+            0.,
+            1.1,
+            0.5,
+            material(MaterialType::Metal(Metal { fuzz: 0.5 }), [0.7, 0.8, 0.9]),
+        ))
+        .add(sphere(
+            3.,
+            0.,
+            2.2,
+            0.5,
+            material(MaterialType::Metal(Metal { fuzz: 1. }), metal),
+        ));
+
+    let camera = Camera::builder()
+        .aspect_ratio(2.0)
+        .image_width(600)
+        .vfov_degrees(60.)
+        .lookfrom(Point::new(-0.5, 0.8, 0.))
+        .lookat(Point::new(3., 0., 0.))
+        .build();
+    (world, camera)
+}
+
+/// Diffuse, glass and metal spheres on a checkered ground
+pub fn checkered_ground() -> (World, Camera) {
+    let ground = Arc::new(Material {
+        material_type: MaterialType::Lambertian(Lambertian),
+        albedo: Texture::Checker(Checker {
+            scale: 1.,
+            even: Color::from([0.2, 0.3, 0.1]),
+            odd: Color::from([0.9, 0.9, 0.9]),
+        }),
+    });
+    let glass = Arc::new(Material {
+        material_type: MaterialType::Dielectric(Dielectric {
+            refraction_index: 1.5,
+            absorption: Default::default(),
+        }),
+        albedo: Texture::Solid(Color::white()),
+    });
+    let mut world = World::new();
+    world
+        .add(sphere(0., -1000., 0., 1000., ground))
+        .add(sphere(
+            6.,
+            1.,
+            -2.5,
+            1.,
+            material(MaterialType::Lambertian(Lambertian), [0.4, 0.2, 0.1]),
+        ))
+        .add(sphere(6., 1., 0., 1., glass))
+        .add(sphere(
+            6.,
+            1.,
+            2.5,
+            1.,
+            material(MaterialType::Metal(Metal { fuzz: 0. }), [0.7, 0.6, 0.5]),
+        ));
+
+    let camera = Camera::builder()
+        .aspect_ratio(16.0 / 9.0)
+        .vfov_degrees(50.)
+        .lookfrom(Point::new(-2., 2., 0.))
+        .lookat(Point::new(6., 1., 0.))
+        .build();
+    (world, camera)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Interval;
+
+    /// The scene has objects and the camera sees one at the center of the image
+    fn assert_framed((world, camera): (World, Camera)) {
+        assert!(!world.objects.is_empty());
+        let ray = camera.ray_through(0.5, 0.5);
+        let interval = Interval {
+            min: 0.001,
+            max: f64::INFINITY,
+        };
+        assert!(world.hit(&ray, interval).is_some());
+    }
+
+    #[test]
+    fn presets_are_framed() {
+        assert_framed(three_close_spheres());
+        assert_framed(cornell_box());
+        assert_framed(metal_showcase());
+        assert_framed(checkered_ground());
+    }
+}
@@ -0,0 +1,441 @@
+use std::path::Path;
+
+use image::{DynamicImage, ImageResult, Rgb32FImage, RgbImage};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::image::{Color, Color3};
+use crate::object::{Point, Vec3};
+
+// Width of the lines of a GridTexture, as a fraction of the space between two lines
+const GRID_LINE_WIDTH: f64 = 0.05;
+// Number of gradients of the Perlin noise, the lattice repeats beyond
+const PERLIN_POINT_COUNT: usize = 256;
+
+/// Color of a surface, varying over its texture coordinates (u, v) or in space.
+/// Stored in scene files as a color for solid textures, or as an object with the texture kind
+/// as its single key. Image textures can't be stored.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Texture {
+    Checker(Checker),
+    Grid(GridTexture),
+    #[serde(skip)]
+    Image(ImageTexture),
+    Noise(NoiseTexture),
+    /// Same color everywhere
+    #[serde(untagged)]
+    Solid(Color),
+}
+
+impl Texture {
+    /// Color at the texture coordinates (u, v), of the point p
+    pub fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        match self {
+            Texture::Solid(color) => *color,
+            Texture::Checker(checker) => checker.value(p),
+            Texture::Grid(grid) => grid.value(u, v),
+            Texture::Image(image) => image.value(u, v),
+            Texture::Noise(noise) => noise.value(p),
+        }
+    }
+
+    /// Average color of the texture
+    pub fn mean_color(&self) -> Color {
+        match self {
+            Texture::Solid(color) => *color,
+            Texture::Checker(checker) => 0.5 * checker.even + 0.5 * checker.odd,
+            Texture::Grid(grid) => grid.base_color,
+            Texture::Image(image) => image.mean_color(),
+            // Noise is centered on 0
+            Texture::Noise(_) => Color::from([0.5, 0.5, 0.5]),
+        }
+    }
+}
+
+/// 3D checkerboard, alternating cubes of side `scale` of the `even` and `odd` colors.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Checker {
+    pub scale: f64,
+    pub even: Color,
+    pub odd: Color,
+}
+
+impl Checker {
+    pub fn value(&self, p: &Point) -> Color {
+        let cell = |coordinate: f64| (coordinate / self.scale).floor() as i64;
+        if (cell(p.x) + cell(p.y) + cell(p.z)) % 2 == 0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+/// Latitude/longitude grid drawn over UV coordinates, to check how textures are mapped on
+/// objects (continuity, seams, poles).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GridTexture {
+    /// Number of lines along u, regularly spaced over [0,1]
+    pub lines_u: u32,
+    /// Number of lines along v, regularly spaced over [0,1]
+    pub lines_v: u32,
+    pub line_color: Color,
+    pub base_color: Color,
+}
+
+impl GridTexture {
+    pub fn value(&self, u: f64, v: f64) -> Color {
+        // Distance to the closest line, in number of spaces between lines
+        let on_line = |coordinate: f64, lines: u32| {
+            let scaled = coordinate * lines as f64;
+            (scaled - scaled.round()).abs() < GRID_LINE_WIDTH / 2.
+        };
+        if on_line(u, self.lines_u) || on_line(v, self.lines_v) {
+            self.line_color
+        } else {
+            self.base_color
+        }
+    }
+}
+
+/// Image mapped over the UV coordinates, (0,0) being the bottom left corner of the image.
+/// Texels are stored as floats so that high dynamic range images (EXR, HDR) keep their bright
+/// texels above 1, 8 bit images being mapped to [0,1].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageTexture {
+    pub image: Rgb32FImage,
+}
+
+impl ImageTexture {
+    pub fn open(path: impl AsRef<Path>) -> ImageResult<ImageTexture> {
+        Ok(ImageTexture {
+            image: image::open(path)?.to_rgb32f(),
+        })
+    }
+
+    /// Color at (u, v), clamped to the displayable range
+    pub fn value(&self, u: f64, v: f64) -> Color {
+        self.radiance(u, v).to_color()
+    }
+
+    /// Unclamped linear color at (u, v), above 1 for the bright texels of HDR images
+    pub fn radiance(&self, u: f64, v: f64) -> Color3 {
+        let (width, height) = self.image.dimensions();
+        if width == 0 || height == 0 {
+            return Color3::black();
+        }
+        // Clamp rather than wrap so the poles of a sphere don't pick up the opposite edge.
+        // Image rows go top down, v goes bottom up.
+        let u = u.clamp(0., 1.);
+        let v = 1. - v.clamp(0., 1.);
+        let x = ((u * width as f64) as u32).min(width - 1);
+        let y = ((v * height as f64) as u32).min(height - 1);
+        let [r, g, b] = self.image.get_pixel(x, y).0;
+        Color3 {
+            r: r as f64,
+            g: g as f64,
+            b: b as f64,
+        }
+    }
+
+    fn mean_color(&self) -> Color {
+        let count = (self.image.width() as u64 * self.image.height() as u64).max(1);
+        let mut sums = [0f64; 3];
+        for pixel in self.image.pixels() {
+            for (sum, channel) in sums.iter_mut().zip(pixel.0) {
+                *sum += channel as f64;
+            }
+        }
+        Color::from(sums.map(|sum| (sum / count as f64).clamp(0., 1.)))
+    }
+}
+
+impl From<RgbImage> for ImageTexture {
+    fn from(image: RgbImage) -> Self {
+        ImageTexture {
+            image: DynamicImage::ImageRgb8(image).to_rgb32f(),
+        }
+    }
+}
+
+/// Smooth pseudo random function of space, made of random gradients on the points of a
+/// lattice, interpolated in between.
+/// Stored in scene files as its seed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "u64", into = "u64")]
+pub struct Perlin {
+    seed: u64,
+    gradients: Vec<Vec3>,
+    // Permutations of the lattice coordinates along each axis, hashed into a gradient index
+    permutations: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    /// The same seed always gives the same noise
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..PERLIN_POINT_COUNT)
+            .map(|_| {
+                Vec3 {
+                    x: rng.gen_range(-1.0..1.0),
+                    y: rng.gen_range(-1.0..1.0),
+                    z: rng.gen_range(-1.0..1.0),
+                }
+                .normalized()
+            })
+            .collect();
+        let mut permutation = || {
+            let mut indices: Vec<usize> = (0..PERLIN_POINT_COUNT).collect();
+            indices.shuffle(&mut rng);
+            indices
+        };
+        Perlin {
+            seed,
+            gradients,
+            permutations: [permutation(), permutation(), permutation()],
+        }
+    }
+
+    /// Noise at `p`, in [-1,1]
+    pub fn noise(&self, p: &Point) -> f64 {
+        let floor = [p.x.floor(), p.y.floor(), p.z.floor()];
+        let fraction = [p.x - floor[0], p.y - floor[1], p.z - floor[2]];
+        // Hermite smoothing, so that the noise has no visible grid artifacts
+        let smooth = fraction.map(|f| f * f * (3. - 2. * f));
+
+        let mut accumulated = 0.;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let lattice_index = |axis: usize| {
+                let coordinate = floor[axis] as i64 + offset[axis] as i64;
+                self.permutations[axis][coordinate.rem_euclid(PERLIN_POINT_COUNT as i64) as usize]
+            };
+            let gradient = self.gradients[lattice_index(0) ^ lattice_index(1) ^ lattice_index(2)];
+            // Position of p relative to the corner
+            let relative = Vec3 {
+                x: fraction[0] - offset[0] as f64,
+                y: fraction[1] - offset[1] as f64,
+                z: fraction[2] - offset[2] as f64,
+            };
+            let weight = |axis: usize| {
+                if offset[axis] == 1 {
+                    smooth[axis]
+                } else {
+                    1. - smooth[axis]
+                }
+            };
+            accumulated += weight(0) * weight(1) * weight(2) * gradient.dot(&relative);
+        }
+        accumulated
+    }
+
+    /// Sum of `octaves` layers of noise, each of twice the frequency and half the amplitude of
+    /// the previous one. Phase shifting it gives marble-like veins.
+    pub fn turbulence(&self, p: &Point, octaves: u32) -> f64 {
+        let mut accumulated = 0.;
+        let mut point = *p;
+        let mut weight = 1.;
+        for _ in 0..octaves {
+            accumulated += weight * self.noise(&point);
+            weight *= 0.5;
+            point = 2. * point;
+        }
+        accumulated.abs()
+    }
+}
+
+impl From<u64> for Perlin {
+    fn from(seed: u64) -> Self {
+        Perlin::new(seed)
+    }
+}
+
+impl From<Perlin> for u64 {
+    fn from(perlin: Perlin) -> Self {
+        perlin.seed
+    }
+}
+
+/// Grayscale Perlin noise, features are about `1 / scale` wide.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoiseTexture {
+    pub scale: f64,
+    pub perlin: Perlin,
+}
+
+impl NoiseTexture {
+    pub fn new(scale: f64, seed: u64) -> NoiseTexture {
+        NoiseTexture {
+            scale,
+            perlin: Perlin::new(seed),
+        }
+    }
+
+    pub fn value(&self, p: &Point) -> Color {
+        let gray = 0.5 * (1. + self.perlin.noise(&(self.scale * *p)));
+        Color::from([gray, gray, gray])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_texture_lines() {
+        let grid = GridTexture {
+            lines_u: 8,
+            lines_v: 4,
+            line_color: Color::white(),
+            base_color: Color::from([0.2, 0.2, 0.2]),
+        };
+        // On the lines
+        assert_eq!(grid.value(0.125, 0.1), grid.line_color);
+        assert_eq!(grid.value(0.6, 0.25), grid.line_color);
+        assert_eq!(grid.value(0., 0.6), grid.line_color);
+        assert_eq!(grid.value(0.3, 1.), grid.line_color);
+        // Between the lines
+        assert_eq!(grid.value(0.0625, 0.125), grid.base_color);
+        assert_eq!(grid.value(0.3, 0.6), grid.base_color);
+    }
+
+    #[test]
+    fn checker_alternates_cells() {
+        let checker = Texture::Checker(Checker {
+            scale: 1.,
+            even: Color::white(),
+            odd: Color::black(),
+        });
+        let at = |x: f64, y: f64, z: f64| checker.value(0., 0., &Point { x, y, z });
+        assert_eq!(at(0.5, 0.5, 0.5), Color::white());
+        // Neighbors along each axis, including negative cells
+        assert_eq!(at(1.5, 0.5, 0.5), Color::black());
+        assert_eq!(at(0.5, 1.5, 0.5), Color::black());
+        assert_eq!(at(0.5, 0.5, -0.5), Color::black());
+        assert_eq!(at(1.5, 1.5, 0.5), Color::white());
+        assert_eq!(at(-0.5, -0.5, 0.5), Color::white());
+
+        let large = Checker {
+            scale: 2.,
+            even: Color::white(),
+            odd: Color::black(),
+        };
+        let origin = Point {
+            x: 1.5,
+            y: 0.5,
+            z: 0.5,
+        };
+        assert_eq!(large.value(&origin), Color::white());
+    }
+
+    #[test]
+    fn image_texture_poles_do_not_wrap() {
+        // Top row is white, bottom row is black
+        let texture = ImageTexture::from(RgbImage::from_fn(4, 2, |_, y| {
+            if y == 0 {
+                image::Rgb([255, 255, 255])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        }));
+        assert_eq!(texture.value(0.5, 1.), Color::white());
+        assert_eq!(texture.value(0.5, 0.), Color::black());
+        // Out of range coordinates are clamped to the closest edge
+        assert_eq!(texture.value(1.5, 1.2), Color::white());
+        assert_eq!(texture.value(-0.5, -0.2), Color::black());
+        assert_eq!(
+            texture.mean_color(),
+            Color {
+                r: 127,
+                g: 127,
+                b: 127
+            }
+        );
+    }
+
+    #[test]
+    fn hdr_image_texture_keeps_bright_texels() {
+        // A sun 10 times brighter than white in the top right corner
+        let image = Rgb32FImage::from_fn(2, 2, |x, y| {
+            if (x, y) == (1, 0) {
+                image::Rgb([10., 9., 8.])
+            } else {
+                image::Rgb([0.25, 0.25, 0.25])
+            }
+        });
+        let path = std::env::temp_dir().join("hdr_image_texture_keeps_bright_texels.exr");
+        image.save(&path).unwrap();
+        let texture = ImageTexture::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let sun = texture.radiance(0.75, 0.75);
+        assert_eq!(
+            sun,
+            Color3 {
+                r: 10.,
+                g: 9.,
+                b: 8.
+            }
+        );
+        assert_eq!(texture.value(0.75, 0.75), Color::white());
+        assert_eq!(texture.radiance(0.25, 0.25).r, 0.25);
+    }
+
+    #[test]
+    fn perlin_noise_is_bounded_and_continuous() {
+        let perlin = Perlin::new(3);
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut distinct = false;
+        for _ in 0..1000 {
+            let p = Point {
+                x: rng.gen_range(-50.0..50.0),
+                y: rng.gen_range(-50.0..50.0),
+                z: rng.gen_range(-50.0..50.0),
+            };
+            let noise = perlin.noise(&p);
+            assert!((-1.0..=1.0).contains(&noise));
+            let nearby = p + Vec3 {
+                x: 1e-4,
+                y: -1e-4,
+                z: 1e-4,
+            };
+            assert!((perlin.noise(&nearby) - noise).abs() < 1e-3);
+            distinct |= (perlin.noise(&(p + p)) - noise).abs() > 0.1;
+        }
+        assert!(distinct);
+        // Zero on the lattice points
+        let lattice = Point {
+            x: 2.,
+            y: -7.,
+            z: 13.,
+        };
+        assert_eq!(perlin.noise(&lattice), 0.);
+        assert_eq!(perlin, Perlin::new(3));
+        assert_ne!(perlin, Perlin::new(5));
+    }
+
+    #[test]
+    fn noise_texture_is_grayscale() {
+        let texture = Texture::Noise(NoiseTexture::new(4., 1));
+        let color = texture.value(
+            0.,
+            0.,
+            &Point {
+                x: 0.3,
+                y: 1.7,
+                z: -2.2,
+            },
+        );
+        assert_eq!(color.r, color.g);
+        assert_eq!(color.g, color.b);
+        let perlin = Perlin::new(1);
+        let p = Point {
+            x: 0.1,
+            y: 0.2,
+            z: 0.3,
+        };
+        assert!(perlin.turbulence(&p, 7) >= 0.);
+        assert!(perlin.turbulence(&p, 7) <= 2.);
+    }
+}
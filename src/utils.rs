@@ -1,11 +1,143 @@
-#[derive(Copy, Clone)]
+use std::cell::RefCell;
+
+use rand::distributions::{Distribution, Standard};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Interval {
     pub min: f64,
     pub max: f64,
 }
 
 impl Interval {
+    /// Contains nothing, enclosing it with another interval gives that other interval
+    pub const EMPTY: Interval = Interval {
+        min: f64::INFINITY,
+        max: f64::NEG_INFINITY,
+    };
+
+    /// Contains every value
+    pub const UNIVERSE: Interval = Interval {
+        min: f64::NEG_INFINITY,
+        max: f64::INFINITY,
+    };
+
+    /// Tightest interval containing both `a` and `b`
+    pub fn enclose(a: Interval, b: Interval) -> Interval {
+        Interval {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+        }
+    }
+
+    /// Whether `x` is in the interval, bounds included
     pub fn contains(&self, x: f64) -> bool {
-        self.min < x && self.max > x
+        self.min <= x && x <= self.max
+    }
+
+    /// Whether `x` is strictly inside the interval, bounds excluded.
+    /// Hits are searched with it, so that a ray doesn't hit again the surface it starts from.
+    pub fn surrounds(&self, x: f64) -> bool {
+        self.min < x && x < self.max
+    }
+
+    pub fn size(&self) -> f64 {
+        self.max - self.min
+    }
+
+    /// Closest value of the interval to `x`
+    pub fn clamp(&self, x: f64) -> f64 {
+        x.max(self.min).min(self.max)
+    }
+
+    /// Interval widened by `delta`, half of it on each side
+    pub fn expand(&self, delta: f64) -> Interval {
+        let padding = delta / 2.;
+        Interval {
+            min: self.min - padding,
+            max: self.max + padding,
+        }
+    }
+}
+
+thread_local! {
+    // Each rendering thread draws from its own generator, reseeded to make renders reproducible
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Random value from the generator of the current thread, floats are in [0,1).
+pub fn random<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+/// Restart the generator of the current thread from `seed`.
+pub fn seed_random(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// Mix two coordinates into a seed (splitmix64 finalizer), so that neighboring coordinates
+/// get unrelated random sequences.
+pub fn hash_seed(seed: u64, a: u64, b: u64) -> u64 {
+    let mut hash =
+        seed ^ a.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ b.wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval_contains_and_surrounds() {
+        let interval = Interval { min: -1., max: 3. };
+        assert!(interval.contains(-1.));
+        assert!(interval.contains(3.));
+        assert!(!interval.surrounds(-1.));
+        assert!(!interval.surrounds(3.));
+        assert!(interval.contains(0.) && interval.surrounds(0.));
+        assert!(!interval.contains(3.5) && !interval.surrounds(3.5));
+    }
+
+    #[test]
+    fn interval_constants() {
+        for x in [f64::NEG_INFINITY, -1e300, 0., 1e300, f64::INFINITY] {
+            assert!(!Interval::EMPTY.contains(x));
+            assert!(Interval::UNIVERSE.contains(x));
+        }
+    }
+
+    #[test]
+    fn interval_enclose() {
+        let a = Interval { min: -1., max: 1. };
+        let b = Interval { min: 0., max: 3. };
+        assert_eq!(Interval::enclose(a, b), Interval { min: -1., max: 3. });
+        assert_eq!(Interval::enclose(b, a), Interval { min: -1., max: 3. });
+        // Disjoint intervals, the gap is enclosed too
+        let c = Interval { min: 5., max: 6. };
+        assert_eq!(Interval::enclose(a, c), Interval { min: -1., max: 6. });
+        assert_eq!(Interval::enclose(Interval::EMPTY, a), a);
+        assert_eq!(Interval::enclose(Interval::UNIVERSE, a), Interval::UNIVERSE);
+    }
+
+    #[test]
+    fn interval_size_clamp_expand() {
+        let interval = Interval { min: -1., max: 3. };
+        assert_eq!(interval.size(), 4.);
+        assert_eq!(interval.clamp(-5.), -1.);
+        assert_eq!(interval.clamp(5.), 3.);
+        assert_eq!(interval.clamp(0.5), 0.5);
+        assert_eq!(
+            interval.expand(1.),
+            Interval {
+                min: -1.5,
+                max: 3.5
+            }
+        );
+        assert_eq!(interval.expand(1.).size(), 5.);
     }
 }